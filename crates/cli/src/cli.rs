@@ -1,26 +1,79 @@
 use std::{fs::canonicalize, io::Error, path::PathBuf};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use config::{layers::LayerPaths, paths::Paths, Storage};
+use diagnostics::Diagnostic;
 use log::{debug, error, warn};
 
 /// Command line arguments for the application
 ///
 /// This struct defines the command line interface using clap.
-/// It currently supports specifying an optional configuration file path.
+/// It supports specifying an optional user configuration file, overriding
+/// single configuration values and inspecting the merged configuration.
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
-    /// Path to configuration file.
+    /// Path to user configuration file, used instead of the default one.
     #[arg(short, long, value_name = "FILE")]
     config: Option<String>,
+    /// Overrides configuration value, e.g. `--set general.storage.path=~/db`.
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    overrides: Vec<String>,
+    /// Keeps database in memory, nothing is written to disk.
+    #[arg(long)]
+    ephemeral: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Inspects configuration.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Inspects ingested hosts.
+    #[command(subcommand)]
+    Host(HostCommand),
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum HostCommand {
+    /// Prints host as ssh config together with `Match` blocks which apply to it.
+    Show {
+        /// Name or alias of the host.
+        name: String,
+        /// Prints options ssh would use, first match wins, with where each one is set.
+        #[arg(long)]
+        effective: bool,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum ConfigCommand {
+    /// Prints configuration merged from every layer.
+    Show {
+        /// Prints where each value came from.
+        #[arg(long)]
+        origin: bool,
+    },
+}
+
+/// Configuration layers and command chosen on command line.
+#[derive(Debug)]
+pub struct Options {
+    /// System, user and project configuration files.
+    pub paths: LayerPaths,
+    /// `--set` arguments, applied after every other layer.
+    pub overrides: Vec<String>,
+    pub command: Option<Command>,
 }
 
 /// Gets the default platform-specific configuration file path
 ///
 /// Returns a String containing the default path where the configuration file
 /// should be located based on the current operating system:
-/// - Windows: %USERPROFILE%/.sshed/config.toml
-/// - Other: $HOME/.config/sshed/config.toml
+/// - Windows: %APPDATA%/sshed/config.toml
+/// - Other: $XDG_CONFIG_HOME/sshed/config.toml, `$XDG_CONFIG_HOME` defaults to $HOME/.config
 ///
 /// # Examples
 ///
@@ -31,17 +84,7 @@ pub struct Args {
 /// assert!(config_path.contains("config.toml"));
 /// ```
 pub fn get_default_config_path() -> String {
-    if cfg!(target_os = "windows") {
-        format!(
-            "{}/.sshed/config.toml",
-            std::env::var("USERPROFILE").unwrap_or_default()
-        )
-    } else {
-        format!(
-            "{}/.config/sshed/config.toml",
-            std::env::var("HOME").unwrap_or_default()
-        )
-    }
+    Paths::new().config_file().to_string_lossy().to_string()
 }
 
 /// Checks if a file exists at the given path
@@ -95,7 +138,7 @@ fn check_config_path(path: Option<String>) -> Result<PathBuf, Error> {
 
     // Try user-provided path first
     if let Some(user_path) = path {
-        if let Ok(_) = try_check_file(&user_path) {
+        if try_check_file(&user_path).is_ok() {
             return canonicalize(&user_path);
         }
     }
@@ -111,31 +154,60 @@ fn check_config_path(path: Option<String>) -> Result<PathBuf, Error> {
     }
 }
 
-/// Parses command line arguments and returns opened configuration file
+/// Parses command line arguments into configuration layers and command
 ///
-/// # Returns
-///
-/// Returns Result containing either opened config File or Error
+/// The user layer is the `--config` file or the default one. Missing files
+/// are left out, configuration then falls back to other layers and defaults.
 ///
 /// # Examples
 ///
+/// ```no_run
+/// let options = cli::parse_args();
+/// println!("Reading {:?}", options.paths.existing());
 /// ```
-/// match cli::parse_args() {
-///     Ok(file) => println!("Successfully opened config file"),
-///     Err(e) => eprintln!("Failed to open config file: {}", e)
-/// }
-/// ```
-pub fn parse_args() -> Result<PathBuf, Error> {
+pub fn parse_args() -> Options {
     let args = Args::parse();
-    match check_config_path(args.config) {
-        Ok(f) => Ok(f),
-        Err(e) => Err(e),
+    let overrides = args.overrides();
+
+    Options {
+        paths: LayerPaths::discover(check_config_path(args.config).ok()),
+        overrides,
+        command: args.command,
+    }
+}
+
+impl Args {
+    /// `--set` arguments together with those implied by other flags.
+    ///
+    /// `--ephemeral` is kept as an override so configuration reloads can't
+    /// switch the database back to disk.
+    fn overrides(&self) -> Vec<String> {
+        let mut overrides = self.overrides.clone();
+        if self.ephemeral {
+            overrides.push(format!("general.storage.path={}", Storage::MEMORY));
+        }
+        overrides
+    }
+}
+
+/// Prints diagnostics to stderr in compiler style, together with the offending lines
+///
+/// # Arguments
+///
+/// * `diagnostics` - Problems found while reading configuration or ssh config files
+pub fn print_diagnostics(diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        match std::fs::read_to_string(&diagnostic.path) {
+            Ok(source) => eprintln!("{}\n", diagnostic.render(&source)),
+            Err(_) => eprintln!("{}\n", diagnostic),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::Write;
     use tempfile::NamedTempFile;
 
     #[test]
@@ -181,4 +253,57 @@ mod tests {
         let result = check_config_path(Some(path));
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_parse_config_show() {
+        let args = Args::try_parse_from([
+            "sshed",
+            "--set",
+            "general.ssh_config_path=~/.ssh/work",
+            "config",
+            "show",
+            "--origin",
+        ])
+        .unwrap();
+
+        assert_eq!(args.overrides, ["general.ssh_config_path=~/.ssh/work"]);
+        assert_eq!(
+            args.command,
+            Some(Command::Config(ConfigCommand::Show { origin: true }))
+        );
+    }
+
+    #[test]
+    fn test_parse_host_show() {
+        let args = Args::try_parse_from(["sshed", "host", "show", "web"]).unwrap();
+
+        assert_eq!(
+            args.command,
+            Some(Command::Host(HostCommand::Show {
+                name: String::from("web"),
+                effective: false,
+            }))
+        );
+
+        let args = Args::try_parse_from(["sshed", "host", "show", "web", "--effective"]).unwrap();
+        assert_eq!(
+            args.command,
+            Some(Command::Host(HostCommand::Show {
+                name: String::from("web"),
+                effective: true,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_ephemeral_overrides_storage() {
+        let args =
+            Args::try_parse_from(["sshed", "--set", "general.storage.path=~/db", "--ephemeral"])
+                .unwrap();
+
+        assert_eq!(
+            args.overrides(),
+            ["general.storage.path=~/db", "general.storage.path=mem://"]
+        );
+    }
 }
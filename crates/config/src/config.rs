@@ -1,8 +1,16 @@
 mod default;
-use serde::Deserialize;
-use std::{fmt::Debug, path::PathBuf};
+pub mod events;
+pub mod expand;
+pub mod layers;
+pub mod paths;
+use diagnostics::{line_column, Diagnostic};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Debug,
+    path::{Path, PathBuf},
+};
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 #[serde(default)]
 pub struct AppConfig {
     pub general: Option<General>,
@@ -16,10 +24,15 @@ impl Default for AppConfig {
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 #[serde(default)]
 pub struct General {
+    /// Single ssh config file or glob, only used when `sources` aren't configured.
     pub ssh_config_path: Option<String>,
+    /// ssh config files or globs read in order, first definition of a host wins.
+    pub sources: Option<Vec<Source>>,
+    /// Glob patterns of files that are skipped when a source is a glob.
+    pub exclude: Option<Vec<String>>,
     pub storage: Option<Storage>,
 }
 
@@ -27,42 +40,273 @@ impl Default for General {
     fn default() -> Self {
         Self {
             ssh_config_path: Some(default::ssh_config_path()),
+            sources: None,
+            exclude: None,
             storage: Some(Storage::default()),
         }
     }
 }
 
-#[derive(Deserialize, Debug)]
+impl General {
+    /// `ssh_config_path` with `~` and environment variables expanded, the field keeps
+    /// the path as written.
+    pub fn expanded_ssh_config_path(&self) -> Option<PathBuf> {
+        self.ssh_config_path.as_deref().map(expand::expand_path)
+    }
+
+    /// Enabled sources, or a single source of `ssh_config_path` when there are no
+    /// `sources` configured.
+    pub fn sources(&self) -> Vec<Source> {
+        match &self.sources {
+            Some(sources) => sources.iter().filter(|s| s.enabled).cloned().collect(),
+            None => self
+                .ssh_config_path
+                .iter()
+                .map(|path| Source {
+                    path: path.clone(),
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+}
+
+/// ssh config file or glob of files, e.g. a read-only checkout of a team repository.
+///
+/// ```toml
+/// [[general.sources]]
+/// name = "infra"
+/// path = "~/work/infra/ssh/**/*"
+/// group = "infra"
+/// tags = ["team"]
+/// read_only = true
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Source {
+    /// Name hosts refer to their source by, `path` when left out.
+    pub name: Option<String>,
+    pub path: String,
+    /// Parent group of every host, groups derived from directories are nested below it.
+    pub group: Option<String>,
+    /// Tags of every host, in addition to ones from `#--[...]` comments.
+    pub tags: Vec<String>,
+    /// Hosts are never written back to files of this source.
+    pub read_only: bool,
+    pub enabled: bool,
+}
+
+impl Default for Source {
+    fn default() -> Self {
+        Self {
+            name: None,
+            path: String::new(),
+            group: None,
+            tags: vec![],
+            read_only: false,
+            enabled: true,
+        }
+    }
+}
+
+impl Source {
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.path)
+    }
+
+    /// `path` with `~` and environment variables expanded.
+    pub fn expanded_path(&self) -> PathBuf {
+        expand::expand_path(&self.path)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 #[serde(default)]
 pub struct Storage {
     pub path: Option<String>,
+    /// Namespace of sshed tables, [`Storage::DEFAULT_NAMESPACE`] when left out.
+    pub namespace: Option<String>,
+    /// Database inside `namespace`, [`Storage::DEFAULT_DATABASE`] when left out.
+    pub database: Option<String>,
+    /// Level `username` is defined on, root when left out.
+    pub auth: Option<AuthLevel>,
+    /// User to sign in as, `root` when left out.
     pub username: Option<String>,
+    /// Server is used anonymously when no password is set.
     pub password: Option<String>,
+    /// Environment variable holding the password, read when `password` is left out.
+    pub password_env: Option<String>,
+    /// File holding the password, read when `password` and `password_env` are left out.
+    pub password_file: Option<String>,
+}
+
+/// SurrealDB user level, the server only accepts sign in on the level user is defined on.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthLevel {
+    #[default]
+    Root,
+    Namespace,
+    Database,
 }
 
 impl Default for Storage {
     fn default() -> Self {
-        #[cfg(not(target_os = "windows"))]
         Self {
-            path: Some(format!(
-                "{}/.config/sshed/db",
-                std::env::var("HOME").unwrap_or_default()
-            )),
+            path: Some(paths::Paths::new().database().to_string_lossy().to_string()),
+            namespace: None,
+            database: None,
+            auth: None,
             username: None,
             password: None,
+            password_env: None,
+            password_file: None,
+        }
+    }
+}
+
+impl Storage {
+    /// Path of in-memory database, nothing is persisted.
+    pub const MEMORY: &'static str = "mem://";
+    pub const DEFAULT_NAMESPACE: &'static str = "hosts";
+    pub const DEFAULT_DATABASE: &'static str = "hosts";
+
+    pub fn namespace(&self) -> &str {
+        self.namespace.as_deref().unwrap_or(Self::DEFAULT_NAMESPACE)
+    }
+
+    pub fn database(&self) -> &str {
+        self.database.as_deref().unwrap_or(Self::DEFAULT_DATABASE)
+    }
+
+    /// `path` with `~` and environment variables expanded.
+    pub fn expanded_path(&self) -> Option<PathBuf> {
+        self.path.as_deref().map(expand::expand_path)
+    }
+}
+
+impl AppConfig {
+    /// Checks settings which can't be left out, toml parser only checks types.
+    pub fn validate(&self, path: &Path) -> Result<(), Diagnostic> {
+        self.validate_with(|_| path.to_path_buf())
+    }
+
+    /// Same as [`AppConfig::validate`], problems are reported at path of the file
+    /// which set dotted key, as given by `path`.
+    pub fn validate_with(&self, path: impl Fn(&str) -> PathBuf) -> Result<(), Diagnostic> {
+        let general = self.general.as_ref();
+
+        if let Some(sources) = general.and_then(|g| g.sources.as_ref()) {
+            for (index, source) in sources.iter().enumerate() {
+                if source.path.is_empty() {
+                    return Err(Diagnostic::error(
+                        &path(&format!("general.sources[{}].path", index)),
+                        0,
+                        0,
+                        format!("`general.sources[{}].path` must not be empty", index),
+                    ));
+                }
+                if sources[..index].iter().any(|s| s.name() == source.name()) {
+                    return Err(Diagnostic::error(
+                        &path("general.sources"),
+                        0,
+                        0,
+                        format!("source `{}` is defined more than once", source.name()),
+                    ));
+                }
+            }
+        } else if matches!(
+            general.and_then(|g| g.ssh_config_path.as_deref()),
+            None | Some("")
+        ) {
+            return Err(Diagnostic::error(
+                &path("general.ssh_config_path"),
+                0,
+                0,
+                "`general.ssh_config_path` must not be empty",
+            ));
+        }
+        for pattern in general
+            .and_then(|g| g.exclude.as_ref())
+            .into_iter()
+            .flatten()
+        {
+            if let Err(e) = glob::Pattern::new(pattern) {
+                return Err(Diagnostic::error(
+                    &path("general.exclude"),
+                    0,
+                    0,
+                    format!(
+                        "`general.exclude` pattern `{}` is invalid at character {}: {}",
+                        pattern,
+                        e.pos + 1,
+                        e.msg
+                    ),
+                ));
+            }
+        }
+        if matches!(
+            general
+                .and_then(|g| g.storage.as_ref())
+                .and_then(|s| s.path.as_deref()),
+            None | Some("")
+        ) {
+            return Err(Diagnostic::error(
+                &path("general.storage.path"),
+                0,
+                0,
+                "`general.storage.path` must not be empty",
+            ));
         }
+        if let Some(storage) = general.and_then(|g| g.storage.as_ref()) {
+            for (key, value) in [
+                ("namespace", &storage.namespace),
+                ("database", &storage.database),
+            ] {
+                if value.as_deref() == Some("") {
+                    return Err(Diagnostic::error(
+                        &path(&format!("general.storage.{}", key)),
+                        0,
+                        0,
+                        format!("`general.storage.{}` must not be empty", key),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
     }
 }
 
-pub fn read_config(path: &PathBuf) -> AppConfig {
-    let contents = std::fs::read_to_string(&path).expect("Failed to read config file");
-    let config: AppConfig = toml::from_str(&contents).expect("Failed to parse TOML");
-    config
+/// Reads configuration file, unreadable, malformed or invalid file is reported as diagnostic.
+pub fn read_config(path: &Path) -> Result<AppConfig, Diagnostic> {
+    let (config, _) = parse_config(path)?;
+    config.validate(path)?;
+
+    Ok(config)
+}
+
+/// Reads configuration file without checking settings which can't be left out,
+/// returns its contents too.
+pub(crate) fn parse_config(path: &Path) -> Result<(AppConfig, String), Diagnostic> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| Diagnostic::error(path, 0, 0, format!("failed to read config file: {}", e)))?;
+
+    let config: AppConfig = toml::from_str(&contents).map_err(|e| {
+        let (line, column) = e
+            .span()
+            .map(|span| line_column(&contents, span.start))
+            .unwrap_or_default();
+        Diagnostic::error(path, line, column, e.message())
+    })?;
+
+    Ok((config, contents))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tempdir::TempDir;
 
     #[test]
     fn test_default_config() {
@@ -81,4 +325,121 @@ mod tests {
         assert!(config.general.is_some());
         assert!(config.general.as_ref().unwrap().ssh_config_path.is_some());
     }
+
+    #[test]
+    fn test_exclude_patterns() {
+        let config = r#"
+            [general]
+            ssh_config_path = "~/.ssh/**/*"
+            exclude = ["known_hosts*", "*.pub", "*.swp"]
+        "#;
+        let config: AppConfig = toml::from_str(config).unwrap();
+        let general = config.general.unwrap();
+        assert_eq!(
+            general.expanded_ssh_config_path(),
+            Some(dirs::home_dir().unwrap().join(".ssh/**/*"))
+        );
+        assert_eq!(general.ssh_config_path.as_deref(), Some("~/.ssh/**/*"));
+
+        let exclude = general.exclude.unwrap();
+        assert_eq!(exclude.len(), 3);
+        assert!(exclude.contains(&"*.pub".to_string()));
+    }
+
+    #[test]
+    fn test_malformed_config() {
+        let dir = TempDir::new("config").unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "[general]\nssh_config_path = 12\n").unwrap();
+
+        let diagnostic = read_config(&path).unwrap_err();
+        assert_eq!((diagnostic.line, diagnostic.column), (2, 19));
+
+        let missing = read_config(&dir.path().join("missing.toml")).unwrap_err();
+        assert_eq!(missing.line, 0);
+
+        std::fs::write(&path, "[general]\nssh_config_path = \"\"\n").unwrap();
+        let invalid = read_config(&path).unwrap_err();
+        assert!(invalid.message.contains("ssh_config_path"));
+
+        std::fs::write(
+            &path,
+            "[general]\nssh_config_path = \"a\"\nexclude = [\"*.swp\", \"[abc\"]\n",
+        )
+        .unwrap();
+        let invalid = read_config(&path).unwrap_err();
+        assert!(invalid.message.contains("`[abc`"));
+    }
+
+    #[test]
+    fn test_sources() {
+        let config = r#"
+            [general]
+            ssh_config_path = "~/.ssh/config"
+
+            [[general.sources]]
+            path = "~/.ssh/config"
+
+            [[general.sources]]
+            name = "infra"
+            path = "~/work/infra/ssh/**/*"
+            group = "infra"
+            tags = ["team"]
+            read_only = true
+
+            [[general.sources]]
+            path = "/old"
+            enabled = false
+        "#;
+        let config: AppConfig = toml::from_str(config).unwrap();
+        config.validate(Path::new("config.toml")).unwrap();
+
+        let sources = config.general.unwrap().sources();
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[0].name(), "~/.ssh/config");
+        assert!(!sources[0].read_only);
+        assert_eq!(sources[1].name(), "infra");
+        assert_eq!(sources[1].tags, ["team"]);
+        assert!(sources[1].read_only);
+
+        // Without sources `ssh_config_path` is the only one
+        let sources = General::default().sources();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(
+            Some(&sources[0].path),
+            General::default().ssh_config_path.as_ref()
+        );
+
+        let duplicate: AppConfig = toml::from_str(
+            "[[general.sources]]\npath = \"a\"\n[[general.sources]]\npath = \"a\"\n",
+        )
+        .unwrap();
+        assert!(duplicate.validate(Path::new("config.toml")).is_err());
+    }
+
+    #[test]
+    fn test_storage_namespace() {
+        let config = r#"
+            [general]
+            ssh_config_path = "~/.ssh/config"
+
+            [general.storage]
+            path = "db.internal:8000"
+            namespace = "infra"
+            auth = "namespace"
+            username = "sshed"
+            password_env = "SSHED_DB_PASSWORD"
+        "#;
+        let config: AppConfig = toml::from_str(config).unwrap();
+        config.validate(Path::new("config.toml")).unwrap();
+
+        let storage = config.general.unwrap().storage.unwrap();
+        assert_eq!(storage.namespace(), "infra");
+        assert_eq!(storage.database(), Storage::DEFAULT_DATABASE);
+        assert_eq!(storage.auth, Some(AuthLevel::Namespace));
+
+        let empty: AppConfig =
+            toml::from_str("[general.storage]\npath = \"db\"\ndatabase = \"\"\n").unwrap();
+        assert!(empty.validate(Path::new("config.toml")).is_err());
+    }
 }
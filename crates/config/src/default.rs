@@ -1,31 +1,23 @@
-#[cfg(target_os = "windows")]
+/// `~/.ssh/config`, the file ssh reads by default.
 pub(crate) fn ssh_config_path() -> String {
-    format!(
-        "{}/.ssh/config",
-        std::env::var("USERPROFILE").unwrap_or_default()
-    )
-}
-
-#[cfg(not(target_os = "windows"))]
-pub(crate) fn ssh_config_path() -> String {
-    format!("{}/.ssh/config", std::env::var("HOME").unwrap_or_default())
+    dirs::home_dir()
+        .unwrap_or_default()
+        .join(".ssh")
+        .join("config")
+        .to_string_lossy()
+        .to_string()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::path::PathBuf;
 
     #[test]
-    #[cfg(target_os = "windows")]
-    fn test_windows_ssh_config_path() {
-        let userprofile = std::env::var("USERPROFILE").unwrap_or_default();
-        assert_eq!(ssh_config_path(), format!("{}/.ssh/config", userprofile));
-    }
-
-    #[test]
-    #[cfg(not(target_os = "windows"))]
-    fn test_unix_ssh_config_path() {
-        let home = std::env::var("HOME").unwrap_or_default();
-        assert_eq!(ssh_config_path(), format!("{}/.ssh/config", home));
+    fn test_ssh_config_path() {
+        assert_eq!(
+            PathBuf::from(ssh_config_path()),
+            dirs::home_dir().unwrap().join(".ssh").join("config")
+        );
     }
 }
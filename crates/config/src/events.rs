@@ -0,0 +1,188 @@
+use std::{
+    path::Path,
+    sync::{mpsc, Arc, Mutex, PoisonError},
+};
+
+use diagnostics::Diagnostic;
+
+use crate::{read_config, AppConfig, Storage};
+
+/// Part of configuration which changed on reload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigChange {
+    /// `ssh_config_path`, `sources` or `exclude`, ssh config has to be scanned again.
+    SshConfig,
+    /// `storage`, database has to be reconnected.
+    Storage,
+}
+
+/// New configuration together with what changed, sent to subscribers of [`ConfigBus`].
+#[derive(Debug, Clone)]
+pub struct ConfigEvent {
+    /// Configuration as it was when change was applied.
+    pub config: Arc<AppConfig>,
+    pub changes: Vec<ConfigChange>,
+}
+
+impl ConfigEvent {
+    pub fn contains(&self, change: ConfigChange) -> bool {
+        self.changes.contains(&change)
+    }
+
+    /// Storage settings of the new configuration, defaults when left out.
+    pub fn storage(&self) -> Storage {
+        self.config
+            .general
+            .as_ref()
+            .and_then(|g| g.storage.clone())
+            .unwrap_or_default()
+    }
+}
+
+type Subscriber = Box<dyn Fn(&ConfigEvent) + Send>;
+
+/// Delivers configuration changes to subsystems which depend on configuration.
+///
+/// Subscribers are called on the thread which publishes the event, so they should
+/// only hand the event over to their own thread.
+#[derive(Default)]
+pub struct ConfigBus {
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+impl std::fmt::Debug for ConfigBus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let count = self.subscribers.lock().map(|s| s.len()).unwrap_or_default();
+        f.debug_struct("ConfigBus")
+            .field("subscribers", &count)
+            .finish()
+    }
+}
+
+impl ConfigBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&self, subscriber: impl Fn(&ConfigEvent) + Send + 'static) {
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(Box::new(subscriber));
+        }
+    }
+
+    /// Subscribes a channel, events published from now on wait in the returned receiver
+    /// until its owner gets to them.
+    pub fn channel(&self) -> mpsc::Receiver<ConfigEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribe(move |event| {
+            let _ = tx.send(event.clone());
+        });
+        rx
+    }
+
+    pub fn publish(&self, event: &ConfigEvent) {
+        if let Ok(subscribers) = self.subscribers.lock() {
+            for subscriber in subscribers.iter() {
+                subscriber(event);
+            }
+        }
+    }
+}
+
+/// Sections in which `new` differs from `old`.
+pub fn changes(old: &AppConfig, new: &AppConfig) -> Vec<ConfigChange> {
+    let old = old.general.clone().unwrap_or_default();
+    let new = new.general.clone().unwrap_or_default();
+    let mut changes = vec![];
+
+    if old.ssh_config_path != new.ssh_config_path
+        || old.sources != new.sources
+        || old.exclude != new.exclude
+    {
+        changes.push(ConfigChange::SshConfig);
+    }
+    if old.storage != new.storage {
+        changes.push(ConfigChange::Storage);
+    }
+
+    changes
+}
+
+/// Reads configuration at `path` and, when it is valid, swaps it into `current`.
+///
+/// Malformed or invalid file is rejected and `current` is left untouched.
+/// Returns `None` when nothing relevant changed.
+pub fn reload(path: &Path, current: &Mutex<AppConfig>) -> Result<Option<ConfigEvent>, Diagnostic> {
+    let new = read_config(path)?;
+
+    Ok(apply(new, current))
+}
+
+/// Swaps already validated `new` configuration into `current`, see [`reload`].
+pub fn apply(new: AppConfig, current: &Mutex<AppConfig>) -> Option<ConfigEvent> {
+    let mut config = current.lock().unwrap_or_else(PoisonError::into_inner);
+    let changes = changes(&config, &new);
+    *config = new;
+
+    if changes.is_empty() {
+        return None;
+    }
+
+    Some(ConfigEvent {
+        config: Arc::new(config.clone()),
+        changes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_reload_rejects_invalid_config() {
+        let dir = TempDir::new("reload").unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "[general]\nssh_config_path = \"~/.ssh/config\"\n").unwrap();
+
+        let current = Mutex::new(read_config(&path).unwrap());
+        let bus = ConfigBus::new();
+        let (tx, rx) = std::sync::mpsc::channel();
+        bus.subscribe(move |event| tx.send(event.changes.clone()).unwrap());
+
+        std::fs::write(
+            &path,
+            "[general]\nssh_config_path = \"~/.ssh/config.d/*\"\n",
+        )
+        .unwrap();
+        let event = reload(&path, &current).unwrap().unwrap();
+        bus.publish(&event);
+        assert_eq!(rx.recv().unwrap(), [ConfigChange::SshConfig]);
+
+        std::fs::write(&path, "[general]\nssh_config_path = [\n").unwrap();
+        assert!(reload(&path, &current).is_err());
+        assert_eq!(
+            current
+                .lock()
+                .unwrap()
+                .general
+                .as_ref()
+                .unwrap()
+                .ssh_config_path,
+            Some(String::from("~/.ssh/config.d/*"))
+        );
+    }
+
+    #[test]
+    fn test_channel_keeps_events_until_received() {
+        let bus = ConfigBus::new();
+        let rx = bus.channel();
+
+        bus.publish(&ConfigEvent {
+            config: Arc::new(AppConfig::default()),
+            changes: vec![ConfigChange::Storage],
+        });
+
+        assert_eq!(rx.recv().unwrap().changes, [ConfigChange::Storage]);
+    }
+}
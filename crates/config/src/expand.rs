@@ -0,0 +1,185 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Values of OpenSSH `%` tokens, tokens without value are left as written.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Tokens {
+    /// `%h`, remote host name.
+    pub host: Option<String>,
+    /// `%r`, remote user name.
+    pub remote_user: Option<String>,
+    /// `%p`, remote port.
+    pub port: Option<u16>,
+    /// `%u`, local user name.
+    pub local_user: Option<String>,
+    /// `%d`, local home directory, also used for `~`.
+    pub home: Option<PathBuf>,
+}
+
+impl Tokens {
+    /// Tokens describing local user, without any remote host.
+    pub fn local() -> Self {
+        Self {
+            local_user: local_user(),
+            home: dirs::home_dir(),
+            ..Default::default()
+        }
+    }
+
+    /// Local tokens together with remote `host`, `user` and `port`.
+    pub fn remote(host: Option<String>, user: Option<String>, port: Option<u16>) -> Self {
+        Self {
+            host,
+            remote_user: user,
+            port,
+            ..Self::local()
+        }
+    }
+}
+
+/// Name of the user running sshed, `%u` token.
+pub fn local_user() -> Option<String> {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .ok()
+}
+
+/// Path as written by user together with its expanded form.
+///
+/// Expanded form is what gets opened, raw one is what gets written back to files.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ExpandedPath {
+    pub raw: String,
+    pub expanded: PathBuf,
+}
+
+impl ExpandedPath {
+    pub fn new(raw: &str, tokens: &Tokens) -> Self {
+        Self {
+            raw: raw.to_string(),
+            expanded: PathBuf::from(expand(raw, tokens)),
+        }
+    }
+}
+
+/// Expands local path, `~`, environment variables, `%u` and `%d` are replaced.
+pub fn expand_path(raw: &str) -> PathBuf {
+    PathBuf::from(expand(raw, &Tokens::local()))
+}
+
+/// Expands leading `~`, `$VAR` and `${VAR}` environment variables and `%h`, `%u`,
+/// `%p`, `%d`, `%r` and `%%` tokens the way OpenSSH does.
+///
+/// Unset variables and unknown tokens are kept as written.
+pub fn expand(raw: &str, tokens: &Tokens) -> String {
+    expand_with(raw, tokens, |name| std::env::var(name).ok())
+}
+
+/// Same as [`expand`], environment variables are read with `var`.
+pub fn expand_with(raw: &str, tokens: &Tokens, var: impl Fn(&str) -> Option<String>) -> String {
+    let home = tokens
+        .home
+        .as_ref()
+        .map(|home| home.to_string_lossy().to_string());
+
+    let mut out = String::new();
+    let mut rest = raw;
+
+    if let Some(home) = &home {
+        if rest == "~" || rest.starts_with("~/") {
+            out.push_str(home);
+            rest = &rest[1..];
+        }
+    }
+
+    let mut chars = rest.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '%' => {
+                let value = match chars.peek().map(|(_, c)| *c) {
+                    Some('%') => Some(String::from("%")),
+                    Some('h') => tokens.host.clone(),
+                    Some('r') => tokens.remote_user.clone(),
+                    Some('p') => tokens.port.map(|p| p.to_string()),
+                    Some('u') => tokens.local_user.clone(),
+                    Some('d') => home.clone(),
+                    _ => None,
+                };
+                match value {
+                    Some(value) => {
+                        out.push_str(&value);
+                        chars.next();
+                    }
+                    None => out.push(c),
+                }
+            }
+            '$' => {
+                let tail = &rest[index + 1..];
+                let (name, length) = match tail.strip_prefix('{') {
+                    Some(braced) => match braced.find('}') {
+                        Some(end) => (&braced[..end], end + 2),
+                        None => ("", 0),
+                    },
+                    None => {
+                        let end = tail
+                            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                            .unwrap_or(tail.len());
+                        (&tail[..end], end)
+                    }
+                };
+
+                match Some(name).filter(|name| !name.is_empty()).and_then(&var) {
+                    Some(value) => {
+                        out.push_str(&value);
+                        for _ in 0..tail[..length].chars().count() {
+                            chars.next();
+                        }
+                    }
+                    None => out.push(c),
+                }
+            }
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let var = |name: &str| (name == "KEYS").then(|| String::from("/keys"));
+        let tokens = Tokens {
+            host: Some(String::from("db1")),
+            remote_user: Some(String::from("deploy")),
+            port: Some(2222),
+            local_user: Some(String::from("me")),
+            home: Some(PathBuf::from("/home/me")),
+        };
+
+        let expand = |raw: &str| expand_with(raw, &tokens, var);
+
+        assert_eq!(expand("~/.ssh/id_rsa"), "/home/me/.ssh/id_rsa");
+        assert_eq!(expand("~"), "/home/me");
+        assert_eq!(expand("~other/x"), "~other/x");
+        assert_eq!(expand("$KEYS/%r@%h:%p"), "/keys/deploy@db1:2222");
+        assert_eq!(expand("${KEYS}_old/%u%%"), "/keys_old/me%");
+        assert_eq!(expand("%d/.ssh/%x"), "/home/me/.ssh/%x");
+        assert_eq!(expand("$UNSET/${UNSET}/$"), "$UNSET/${UNSET}/$");
+    }
+
+    #[test]
+    fn test_expanded_path_keeps_raw() {
+        let tokens = Tokens {
+            home: Some(PathBuf::from("/home/me")),
+            ..Default::default()
+        };
+        let path = ExpandedPath::new("~/.ssh/id_%h", &tokens);
+
+        assert_eq!(path.raw, "~/.ssh/id_%h");
+        assert_eq!(path.expanded, PathBuf::from("/home/me/.ssh/id_%h"));
+    }
+}
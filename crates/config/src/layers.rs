@@ -0,0 +1,608 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use diagnostics::Diagnostic;
+use toml::{Table, Value};
+
+use crate::{parse_config, paths, AppConfig};
+
+/// Prefix of environment variables which override configuration.
+pub const ENV_PREFIX: &str = "SSHED_";
+
+/// Project configuration, looked up in working directory and its parents.
+pub const PROJECT_FILE: &str = ".sshed.toml";
+
+/// Where a configuration value came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    System(PathBuf),
+    User(PathBuf),
+    Project(PathBuf),
+    /// Name of environment variable.
+    Environment(String),
+    /// `--set` argument.
+    Cli(String),
+}
+
+impl Origin {
+    /// Location diagnostics about values of this origin point to.
+    pub fn path(&self) -> PathBuf {
+        match self {
+            Origin::Default => PathBuf::from("<default>"),
+            Origin::System(path) | Origin::User(path) | Origin::Project(path) => path.clone(),
+            Origin::Environment(name) => PathBuf::from(format!("${}", name)),
+            Origin::Cli(argument) => PathBuf::from(format!("--set {}", argument)),
+        }
+    }
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::System(path) => write!(f, "system {}", path.display()),
+            Origin::User(path) => write!(f, "user {}", path.display()),
+            Origin::Project(path) => write!(f, "project {}", path.display()),
+            Origin::Environment(name) => write!(f, "environment ${}", name),
+            Origin::Cli(argument) => write!(f, "command line --set {}", argument),
+        }
+    }
+}
+
+/// Configuration files of every layer, from the least to the most specific.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LayerPaths {
+    pub system: Option<PathBuf>,
+    pub user: Option<PathBuf>,
+    pub project: Option<PathBuf>,
+}
+
+impl LayerPaths {
+    /// System file, `user` and project file found from working directory.
+    pub fn discover(user: Option<PathBuf>) -> Self {
+        Self {
+            system: Some(system_path()),
+            user,
+            project: std::env::current_dir()
+                .ok()
+                .and_then(|dir| find_project(&dir)),
+        }
+    }
+
+    /// Files which exist, in the order they are applied.
+    pub fn existing(&self) -> Vec<PathBuf> {
+        self.all()
+            .into_iter()
+            .filter(|path| path.is_file())
+            .collect()
+    }
+
+    /// Files of every layer, whether they exist or not.
+    pub fn all(&self) -> Vec<PathBuf> {
+        [&self.system, &self.user, &self.project]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    /// Directories layer files are in or would be created in, only existing ones.
+    pub fn directories(&self) -> Vec<PathBuf> {
+        let mut directories: Vec<PathBuf> = self
+            .all()
+            .iter()
+            .filter_map(|path| path.parent())
+            .map(|dir| match dir.as_os_str().is_empty() {
+                true => PathBuf::from("."),
+                false => dir.to_path_buf(),
+            })
+            .filter(|dir| dir.is_dir())
+            .collect();
+
+        directories.sort();
+        directories.dedup();
+        directories
+    }
+}
+
+/// Company-wide configuration shipped by administrators.
+pub fn system_path() -> PathBuf {
+    if cfg!(target_os = "windows") {
+        PathBuf::from(std::env::var("PROGRAMDATA").unwrap_or_default()).join("sshed/config.toml")
+    } else {
+        PathBuf::from("/etc/sshed/config.toml")
+    }
+}
+
+/// Nearest [`PROJECT_FILE`] in `start` or any of its parents.
+pub fn find_project(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_FILE))
+        .find(|path| path.is_file())
+}
+
+/// Values set by a single origin.
+#[derive(Debug, Clone)]
+pub struct Layer {
+    pub origin: Origin,
+    pub table: Table,
+    /// Dotted key and value as written, when the value isn't a string and its type
+    /// was guessed from how it looks. Only environment and arguments are guessed.
+    pub guessed: Option<(String, String)>,
+}
+
+impl Layer {
+    /// Reads configuration file, `None` when there is no file at `path`.
+    pub fn file(path: &Path, origin: Origin) -> Result<Option<Self>, Diagnostic> {
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let (_, contents) = parse_config(path)?;
+        let table = toml::from_str(&contents)
+            .map_err(|e| Diagnostic::error(path, 0, 0, e.message().to_string()))?;
+
+        Ok(Some(Self {
+            origin,
+            table,
+            guessed: None,
+        }))
+    }
+
+    /// One layer per `SSHED_*` variable, `__` separates nested keys, so
+    /// `SSHED_GENERAL__STORAGE__PATH` sets `general.storage.path`.
+    pub fn environment(vars: impl IntoIterator<Item = (String, String)>) -> Vec<Self> {
+        let mut vars: Vec<(String, String)> = vars
+            .into_iter()
+            .filter(|(name, _)| name.len() > ENV_PREFIX.len() && name.starts_with(ENV_PREFIX))
+            .collect();
+        // Environment has no order of its own
+        vars.sort();
+
+        vars.into_iter()
+            .map(|(name, value)| {
+                let key = name[ENV_PREFIX.len()..].to_lowercase().replace("__", ".");
+                Self::guess(&key, &value, Origin::Environment(name))
+            })
+            .collect()
+    }
+
+    /// Layer of `key=value` argument, e.g. `general.ssh_config_path=~/.ssh/work`.
+    pub fn argument(argument: &str) -> Result<Self, Diagnostic> {
+        let origin = Origin::Cli(argument.to_string());
+        let Some((key, value)) = argument
+            .split_once('=')
+            .filter(|(k, _)| !k.trim().is_empty())
+        else {
+            return Err(Diagnostic::error(
+                &origin.path(),
+                0,
+                0,
+                "expected KEY=VALUE, e.g. general.ssh_config_path=~/.ssh/config",
+            ));
+        };
+
+        Ok(Self::guess(key.trim(), value.trim(), origin))
+    }
+
+    /// Layer setting `key` to `value` parsed as TOML.
+    fn guess(key: &str, value: &str, origin: Origin) -> Self {
+        let parsed = parse_value(value);
+        let guessed = (!parsed.is_str()).then(|| (key.to_string(), value.to_string()));
+
+        Self {
+            table: nested(key, parsed),
+            origin,
+            guessed,
+        }
+    }
+}
+
+/// `value` as TOML, e.g. `true` or `["a", "b"]`, anything else is a string.
+fn parse_value(value: &str) -> Value {
+    toml::from_str::<Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+/// Table with `value` at dotted `key`.
+fn nested(key: &str, value: Value) -> Table {
+    let mut value = value;
+    for segment in key.rsplit('.') {
+        let mut table = Table::new();
+        table.insert(segment.to_string(), value);
+        value = Value::Table(table);
+    }
+
+    match value {
+        Value::Table(table) => table,
+        _ => Table::new(),
+    }
+}
+
+/// Configuration merged from every layer, together with origin of every value.
+#[derive(Debug, Clone)]
+pub struct LayeredConfig {
+    pub config: AppConfig,
+    /// Origin of values and arrays set by layers, keyed by dotted path.
+    origins: BTreeMap<String, Origin>,
+}
+
+impl LayeredConfig {
+    /// Deep-merges `layers`, later layers override values of earlier ones.
+    ///
+    /// Tables are merged key by key, arrays are replaced as a whole.
+    pub fn merge(layers: Vec<Layer>) -> Result<Self, Diagnostic> {
+        let mut table = Table::new();
+        let mut origins = BTreeMap::new();
+        let mut guesses = vec![];
+        let mut last = Origin::Default;
+
+        for layer in layers {
+            merge(&mut table, layer.table, "", &layer.origin, &mut origins);
+            guesses.extend(layer.guessed.map(|guess| (guess, layer.origin.clone())));
+            last = layer.origin;
+        }
+        // Guesses overridden by later layers don't matter
+        let mut guessed: BTreeMap<String, String> = guesses
+            .into_iter()
+            .filter(|((key, _), origin)| origins.get(key) == Some(origin))
+            .map(|(guess, _)| guess)
+            .collect();
+
+        // Files were checked on their own, only environment and arguments can be wrong here
+        let config: AppConfig = loop {
+            let error = match Value::Table(table.clone()).try_into() {
+                Ok(config) => break config,
+                Err(e) => e,
+            };
+            let key = error_key(&error);
+
+            // `123456` may be meant as text, e.g. for a password
+            if let Some(raw) = key.as_ref().and_then(|key| guessed.remove(key)) {
+                replace(
+                    &mut table,
+                    key.as_deref().unwrap_or_default(),
+                    Value::String(raw),
+                );
+                continue;
+            }
+
+            let path = match &key {
+                Some(key) => origin_of(&origins, key).path(),
+                None => last.path(),
+            };
+            return Err(Diagnostic::error(&path, 0, 0, error.message()));
+        };
+        config.validate_with(|key| origin_of(&origins, key).path())?;
+
+        Ok(Self { config, origins })
+    }
+
+    /// Points storage left at its default path to `database`, which may be the
+    /// database of an earlier version.
+    fn use_default_database(&mut self, database: PathBuf) {
+        if self.origin("general.storage.path") != &Origin::Default {
+            return;
+        }
+        let general = self.config.general.get_or_insert_with(Default::default);
+        let storage = general.storage.get_or_insert_with(Default::default);
+        storage.path = Some(database.to_string_lossy().to_string());
+    }
+
+    /// Origin of value at dotted `key`, e.g. `general.storage.path`.
+    pub fn origin(&self, key: &str) -> &Origin {
+        origin_of(&self.origins, key)
+    }
+
+    /// Effective configuration as `key = value` lines, optionally followed by origin
+    /// of every value. Passwords are masked.
+    pub fn show(&self, with_origin: bool) -> String {
+        let mut values = vec![];
+        if let Ok(value) = Value::try_from(&self.config) {
+            flatten("", &value, &mut values);
+        }
+
+        let mut out = String::new();
+        for (key, value) in values {
+            let value = match key.ends_with(".password") {
+                true => String::from("\"***\""),
+                false => value,
+            };
+            match with_origin {
+                true => out.push_str(&format!("{} = {}  # {}\n", key, value, self.origin(&key))),
+                false => out.push_str(&format!("{} = {}\n", key, value)),
+            }
+        }
+
+        out
+    }
+}
+
+/// Merges default configuration, files of `paths`, `SSHED_*` environment variables
+/// and `overrides` given as `key=value`, in this order.
+pub fn load(paths: &LayerPaths, overrides: &[String]) -> Result<LayeredConfig, Diagnostic> {
+    let files = [
+        (&paths.system, Origin::System as fn(PathBuf) -> Origin),
+        (&paths.user, Origin::User),
+        (&paths.project, Origin::Project),
+    ];
+
+    let mut layers = vec![];
+    for (path, origin) in files {
+        if let Some(path) = path {
+            layers.extend(Layer::file(path, origin(path.clone()))?);
+        }
+    }
+    layers.extend(Layer::environment(std::env::vars()));
+    for argument in overrides {
+        layers.push(Layer::argument(argument)?);
+    }
+
+    let mut layered = LayeredConfig::merge(layers)?;
+    layered.use_default_database(paths::default_database());
+    Ok(layered)
+}
+
+fn origin_of<'a>(origins: &'a BTreeMap<String, Origin>, key: &str) -> &'a Origin {
+    origins
+        .iter()
+        .filter(|(set, _)| is_within(key, set))
+        .max_by_key(|(set, _)| set.len())
+        .map(|(_, origin)| origin)
+        .unwrap_or(&Origin::Default)
+}
+
+/// Dotted key of the value toml failed to deserialize, when it's known.
+fn error_key(error: &toml::de::Error) -> Option<String> {
+    error
+        .to_string()
+        .lines()
+        .find_map(|line| line.strip_prefix("in `")?.strip_suffix('`'))
+        .map(String::from)
+}
+
+/// Replaces value at dotted `key` of `table`, missing tables are left as they are.
+fn replace(table: &mut Table, key: &str, value: Value) {
+    match key.split_once('.') {
+        Some((first, rest)) => {
+            if let Some(Value::Table(inner)) = table.get_mut(first) {
+                replace(inner, rest, value);
+            }
+        }
+        None => {
+            table.insert(key.to_string(), value);
+        }
+    }
+}
+
+fn merge(
+    into: &mut Table,
+    from: Table,
+    prefix: &str,
+    origin: &Origin,
+    origins: &mut BTreeMap<String, Origin>,
+) {
+    for (key, value) in from {
+        let path = match prefix {
+            "" => key.clone(),
+            prefix => format!("{}.{}", prefix, key),
+        };
+
+        match (into.get_mut(&key), value) {
+            (Some(Value::Table(existing)), Value::Table(table)) => {
+                merge(existing, table, &path, origin, origins)
+            }
+            (_, value) => {
+                origins.retain(|set, _| !is_within(set, &path));
+                record(&path, &value, origin, origins);
+                into.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Records `origin` of every value in `value`, arrays count as a single value.
+fn record(path: &str, value: &Value, origin: &Origin, origins: &mut BTreeMap<String, Origin>) {
+    match value {
+        Value::Table(table) => {
+            for (key, value) in table {
+                record(&format!("{}.{}", path, key), value, origin, origins);
+            }
+        }
+        _ => {
+            origins.insert(path.to_string(), origin.clone());
+        }
+    }
+}
+
+/// `key` is `parent` or lies below it, e.g. `general.sources[0].path` below `general.sources`.
+fn is_within(key: &str, parent: &str) -> bool {
+    key.strip_prefix(parent)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.') || rest.starts_with('['))
+}
+
+fn flatten(key: &str, value: &Value, out: &mut Vec<(String, String)>) {
+    let join = |name: &str| match key {
+        "" => name.to_string(),
+        key => format!("{}.{}", key, name),
+    };
+
+    match value {
+        Value::Table(table) => {
+            for (name, value) in table {
+                flatten(&join(name), value, out);
+            }
+        }
+        Value::Array(items) if !items.is_empty() && items.iter().all(Value::is_table) => {
+            for (index, item) in items.iter().enumerate() {
+                flatten(&format!("{}[{}]", key, index), item, out);
+            }
+        }
+        value => out.push((key.to_string(), value.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn layer(origin: Origin, toml: &str) -> Layer {
+        Layer {
+            origin,
+            table: toml::from_str(toml).unwrap(),
+            guessed: None,
+        }
+    }
+
+    #[test]
+    fn test_layers_deep_merge() {
+        let system = Origin::System(PathBuf::from("/etc/sshed/config.toml"));
+        let user = Origin::User(PathBuf::from("config.toml"));
+        let mut layers = vec![
+            layer(
+                system.clone(),
+                "[general]\nexclude = [\"*.pub\"]\n[general.storage]\npath = \"/srv/db\"\nusername = \"ops\"\n",
+            ),
+            layer(user.clone(), "[general.storage]\npath = \"~/db\"\n"),
+        ];
+        layers.extend(Layer::environment([
+            (
+                String::from("SSHED_GENERAL__EXCLUDE"),
+                String::from("[\"*.swp\"]"),
+            ),
+            (String::from("HOME"), String::from("/home/me")),
+        ]));
+        layers.push(Layer::argument("general.ssh_config_path=~/.ssh/work").unwrap());
+
+        let layered = LayeredConfig::merge(layers).unwrap();
+        let general = layered.config.general.as_ref().unwrap();
+        let storage = general.storage.as_ref().unwrap();
+
+        assert_eq!(storage.path.as_deref(), Some("~/db"));
+        assert_eq!(storage.username.as_deref(), Some("ops"));
+        assert_eq!(general.exclude, Some(vec![String::from("*.swp")]));
+        assert_eq!(general.ssh_config_path.as_deref(), Some("~/.ssh/work"));
+
+        assert_eq!(layered.origin("general.storage.path"), &user);
+        assert_eq!(layered.origin("general.storage.username"), &system);
+        assert_eq!(
+            layered.origin("general.exclude"),
+            &Origin::Environment(String::from("SSHED_GENERAL__EXCLUDE"))
+        );
+        assert_eq!(layered.origin("general.storage.password"), &Origin::Default);
+
+        let shown = layered.show(true);
+        assert!(shown.contains("general.storage.path = \"~/db\"  # user config.toml\n"));
+        assert!(shown.contains(
+            "general.ssh_config_path = \"~/.ssh/work\"  # command line --set general.ssh_config_path=~/.ssh/work\n"
+        ));
+    }
+
+    #[test]
+    fn test_default_database_replaces_only_default_path() {
+        let legacy = PathBuf::from("/home/me/.config/sshed/db");
+        let mut layered = LayeredConfig::merge(vec![]).unwrap();
+        layered.use_default_database(legacy.clone());
+        let storage = layered.config.general.unwrap().storage.unwrap();
+        assert_eq!(storage.expanded_path(), Some(legacy.clone()));
+
+        let mut layered = LayeredConfig::merge(vec![layer(
+            Origin::User(PathBuf::from("config.toml")),
+            "[general.storage]\npath = \"~/db\"\n",
+        )])
+        .unwrap();
+        layered.use_default_database(legacy);
+        let storage = layered.config.general.unwrap().storage.unwrap();
+        assert_eq!(storage.path.as_deref(), Some("~/db"));
+    }
+
+    #[test]
+    fn test_guessed_values_fall_back_to_text() {
+        let mut layers = Layer::environment([
+            (
+                String::from("SSHED_GENERAL__SSH_CONFIG_PATH"),
+                String::from("12"),
+            ),
+            (
+                String::from("SSHED_GENERAL__STORAGE__PASSWORD"),
+                String::from("123456"),
+            ),
+        ]);
+        layers.push(Layer::argument("general.storage.path=2024").unwrap());
+
+        let layered = LayeredConfig::merge(layers).unwrap();
+        let general = layered.config.general.as_ref().unwrap();
+        let storage = general.storage.as_ref().unwrap();
+
+        assert_eq!(general.ssh_config_path.as_deref(), Some("12"));
+        assert_eq!(storage.password.as_deref(), Some("123456"));
+        assert_eq!(storage.path.as_deref(), Some("2024"));
+    }
+
+    #[test]
+    fn test_invalid_layers() {
+        assert!(Layer::argument("general.ssh_config_path").is_err());
+
+        let mut layers = vec![layer(
+            Origin::User(PathBuf::from("config.toml")),
+            "[general]\nssh_config_path = \"~/.ssh/config\"\n",
+        )];
+        layers.extend(Layer::environment([(
+            String::from("SSHED_GENERAL__SOURCES"),
+            String::from("12"),
+        )]));
+        layers.push(Layer::argument("general.exclude=[\"*.pub\"]").unwrap());
+        let diagnostic = LayeredConfig::merge(layers).unwrap_err();
+        assert_eq!(diagnostic.path, PathBuf::from("$SSHED_GENERAL__SOURCES"));
+
+        // Invalid value is blamed on the layer which set it, not on the last one
+        let mut layers = vec![layer(
+            Origin::User(PathBuf::from("config.toml")),
+            "[general]\nssh_config_path = \"~/.ssh/config\"\n",
+        )];
+        layers.extend(Layer::environment([(
+            String::from("SSHED_GENERAL__STORAGE__PATH"),
+            String::from("\"\""),
+        )]));
+        layers.push(Layer::argument("general.exclude=[\"*.pub\"]").unwrap());
+        let diagnostic = LayeredConfig::merge(layers).unwrap_err();
+        assert_eq!(
+            diagnostic.path,
+            PathBuf::from("$SSHED_GENERAL__STORAGE__PATH")
+        );
+    }
+
+    #[test]
+    fn test_layer_directories() {
+        let dir = TempDir::new("layers").unwrap();
+        let paths = LayerPaths {
+            system: Some(dir.path().join("missing/config.toml")),
+            user: Some(dir.path().join("config.toml")),
+            project: None,
+        };
+
+        // User file doesn't exist yet, its directory is still watched
+        assert!(paths.existing().is_empty());
+        assert_eq!(paths.all().len(), 2);
+        assert_eq!(paths.directories(), [dir.path().to_path_buf()]);
+    }
+
+    #[test]
+    fn test_find_project() {
+        let root = TempDir::new("project").unwrap();
+        let dir = root.path().join("nested");
+        std::fs::create_dir_all(&dir).unwrap();
+        let project = root.path().join(PROJECT_FILE);
+        std::fs::write(&project, "").unwrap();
+
+        assert_eq!(find_project(&dir), Some(project));
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+/// Name of directory sshed creates in every base directory.
+const APP_DIR: &str = "sshed";
+
+/// Directories sshed keeps its files in.
+///
+/// On Linux and macOS they follow the XDG base directory specification, on Windows
+/// configuration is roaming and everything else stays local to the machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Paths {
+    /// `config.toml`.
+    pub config: PathBuf,
+    /// Embedded database.
+    pub data: PathBuf,
+    /// Hashes of parsed files, can be removed at any time.
+    pub cache: PathBuf,
+    /// Logs and history.
+    pub state: PathBuf,
+}
+
+impl Paths {
+    /// Directories of current user.
+    pub fn new() -> Self {
+        Self::resolve(
+            |name| std::env::var_os(name),
+            dirs::home_dir().unwrap_or_default(),
+        )
+    }
+
+    /// Default user configuration file.
+    pub fn config_file(&self) -> PathBuf {
+        self.config.join("config.toml")
+    }
+
+    /// Default location of embedded database.
+    pub fn database(&self) -> PathBuf {
+        self.data.join("db")
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    fn resolve(var: impl Fn(&str) -> Option<OsString>, home: PathBuf) -> Self {
+        let base = |name: &str, default: &str| base_dir(&var, &home, name, default);
+
+        Self {
+            config: base("XDG_CONFIG_HOME", ".config"),
+            data: base("XDG_DATA_HOME", ".local/share"),
+            cache: base("XDG_CACHE_HOME", ".cache"),
+            state: base("XDG_STATE_HOME", ".local/state"),
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    fn resolve(var: impl Fn(&str) -> Option<OsString>, home: PathBuf) -> Self {
+        let base = |name: &str, default: &str| base_dir(&var, &home, name, default);
+        let local = base("LOCALAPPDATA", "AppData/Local");
+
+        Self {
+            config: base("APPDATA", "AppData/Roaming"),
+            data: local.join("data"),
+            cache: local.join("cache"),
+            state: local.join("state"),
+        }
+    }
+}
+
+/// sshed directory below base directory in variable `name`, or below `default`
+/// in `home` when the variable isn't set.
+fn base_dir(
+    var: &impl Fn(&str) -> Option<OsString>,
+    home: &Path,
+    name: &str,
+    default: &str,
+) -> PathBuf {
+    // Relative paths are invalid according to the XDG specification and are ignored
+    var(name)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .unwrap_or_else(|| home.join(default))
+        .join(APP_DIR)
+}
+
+/// Database used when storage path is left out, chosen on the first call.
+///
+/// Earlier versions kept it in `~/.config/sshed/db`, that one stays in use until
+/// it's moved to [`Paths::database`]. Later calls return the same choice, so
+/// reloaded configuration never switches databases.
+pub fn default_database() -> PathBuf {
+    static DATABASE: OnceLock<PathBuf> = OnceLock::new();
+    DATABASE
+        .get_or_init(|| {
+            let legacy = dirs::home_dir()
+                .unwrap_or_default()
+                .join(".config/sshed/db");
+            select_database(Paths::new().database(), legacy)
+        })
+        .clone()
+}
+
+fn select_database(current: PathBuf, legacy: PathBuf) -> PathBuf {
+    if current.exists() || !legacy.is_dir() || current == legacy {
+        return current;
+    }
+
+    log::warn!(
+        "Using database at {} of an earlier version, move it to {} to follow platform conventions",
+        legacy.display(),
+        current.display()
+    );
+    legacy
+}
+
+impl Default for Paths {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    #[cfg(not(target_os = "windows"))]
+    fn test_xdg_paths() {
+        let vars = |name: &str| match name {
+            "XDG_CONFIG_HOME" => Some(OsString::from("/xdg/config")),
+            "XDG_DATA_HOME" => Some(OsString::from("relative/data")),
+            _ => None,
+        };
+        let paths = Paths::resolve(vars, PathBuf::from("/home/me"));
+
+        assert_eq!(
+            paths.config_file(),
+            PathBuf::from("/xdg/config/sshed/config.toml")
+        );
+        assert_eq!(
+            paths.database(),
+            PathBuf::from("/home/me/.local/share/sshed/db")
+        );
+        assert_eq!(paths.cache, PathBuf::from("/home/me/.cache/sshed"));
+        assert_eq!(paths.state, PathBuf::from("/home/me/.local/state/sshed"));
+    }
+
+    #[test]
+    fn test_legacy_database_is_kept() {
+        let dir = TempDir::new("paths").unwrap();
+        let current = dir.path().join("share/sshed/db");
+        let legacy = dir.path().join("config/sshed/db");
+
+        assert_eq!(select_database(current.clone(), legacy.clone()), current);
+
+        std::fs::create_dir_all(&legacy).unwrap();
+        assert_eq!(select_database(current.clone(), legacy.clone()), legacy);
+
+        std::fs::create_dir_all(&current).unwrap();
+        assert_eq!(select_database(current.clone(), legacy), current);
+    }
+
+    #[test]
+    #[cfg(target_os = "windows")]
+    fn test_windows_paths() {
+        let vars = |name: &str| match name {
+            "APPDATA" => Some(OsString::from("C:/Users/me/AppData/Roaming")),
+            _ => None,
+        };
+        let paths = Paths::resolve(vars, PathBuf::from("C:/Users/me"));
+
+        assert_eq!(
+            paths.config_file(),
+            PathBuf::from("C:/Users/me/AppData/Roaming/sshed/config.toml")
+        );
+        assert_eq!(
+            paths.database(),
+            PathBuf::from("C:/Users/me/AppData/Local/sshed/data/db")
+        );
+        assert_eq!(
+            paths.cache,
+            PathBuf::from("C:/Users/me/AppData/Local/sshed/cache")
+        );
+        assert_eq!(
+            paths.state,
+            PathBuf::from("C:/Users/me/AppData/Local/sshed/state")
+        );
+    }
+}
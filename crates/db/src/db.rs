@@ -1,10 +1,15 @@
-use config::Storage;
+pub mod migrations;
+
+use std::path::PathBuf;
+
+use config::{expand::expand_path, AuthLevel, Storage};
 use surrealdb::{
     engine::{
-        local::{Db, RocksDb},
+        any::{self, Any},
+        local::{Db, Mem, RocksDb},
         remote::ws::{Client, Ws},
     },
-    opt::auth::Root,
+    opt::auth::{Database, Namespace, Root},
     Connection, Surreal,
 };
 use tokio::runtime::Runtime;
@@ -17,183 +22,325 @@ pub async fn set_remote_db(addr: &str) -> Result<Surreal<Client>, surrealdb::Err
     Surreal::new::<Ws>(addr).await
 }
 
-pub struct DbRuntime {
-    pub db: Surreal<Client>,
+/// Database connection together with runtime driving its futures.
+///
+/// The app uses the [`Any`] engine, which is either embedded or remote depending
+/// on `Storage.path`, code taking `DbRuntime<C>` runs unchanged against both.
+pub struct DbRuntime<C: Connection = Any> {
+    pub db: Surreal<C>,
     pub runtime: Runtime,
 }
 
-impl DbRuntime {
-    pub fn new() -> Self {
-        let rt = Runtime::new().unwrap();
-        let db = rt.block_on(async {
-            let db = set_remote_db("127.0.0.1:8000").await.unwrap();
-            let _ = login(&db, "root", "root").await.unwrap();
-            let _ = set_namespace(&db).await.unwrap();
-            let _ = define_schema(&db).await.unwrap();
-            db
-        });
+impl DbRuntime<Any> {
+    /// Connects to database described by `storage`.
+    pub fn new(storage: &Storage) -> surrealdb::Result<Self> {
+        let rt = Runtime::new()
+            .map_err(|e| invalid_storage(format!("failed to start database runtime: {}", e)))?;
+        let db = rt.block_on(open(storage))?;
 
-        Self { db, runtime: rt }
+        Ok(Self { db, runtime: rt })
     }
-}
 
-#[derive(Debug)]
-enum AddressType {
-    WebAddress,
-    FilePath,
-    Invalid,
+    /// Connects to database described by `storage` and replaces the current
+    /// connection once the new one is ready, on error the current one is kept.
+    pub fn reconnect(&mut self, storage: &Storage) -> surrealdb::Result<()> {
+        self.db = self.runtime.block_on(open(storage))?;
+        Ok(())
+    }
 }
 
-fn is_valid_ip(ip: &str) -> bool {
-    let parts: Vec<&str> = ip.split('.').collect();
-    if parts.len() != 4 {
-        return false;
+impl<C: Connection> DbRuntime<C> {
+    /// Wraps connection opened by the caller, e.g. to a concrete engine in tests.
+    pub fn with_connection(db: Surreal<C>, runtime: Runtime) -> Self {
+        Self { db, runtime }
     }
+}
 
-    parts.iter().all(|part| {
-        if let Ok(_num) = part.parse::<u8>() {
-            true
-        } else {
-            false
-        }
-    })
+/// Where `Storage.path` points to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Engine {
+    /// SurrealDB server, `host:port` or `ws://` and `wss://` URL.
+    Remote(String),
+    /// RocksDB database directory, no server process is needed.
+    Embedded(PathBuf),
+    /// Nothing is persisted, see [`Storage::MEMORY`].
+    Memory,
 }
 
-fn is_valid_domain(domain: &str) -> bool {
-    if domain.is_empty() || domain.starts_with('.') || domain.ends_with('.') {
-        return false;
+impl Engine {
+    pub fn from_storage(storage: &Storage) -> surrealdb::Result<Self> {
+        if storage.path.as_deref() == Some(Storage::MEMORY) {
+            return Ok(Engine::Memory);
+        }
+
+        let path = storage
+            .expanded_path()
+            .ok_or_else(|| invalid_storage(String::from("storage path is not set")))?;
+        let address = path.to_string_lossy();
+
+        match identify_address(&address) {
+            AddressType::WebAddress => Ok(Engine::Remote(address.to_string())),
+            AddressType::FilePath => Ok(Engine::Embedded(path)),
+            AddressType::Invalid => Err(invalid_storage(String::from("storage path is empty"))),
+        }
     }
 
-    let parts: Vec<&str> = domain.split('.').collect();
-    if parts.len() < 2 {
-        return false;
+    /// Address [`surrealdb::engine::any::connect`] understands.
+    pub fn endpoint(&self) -> String {
+        match self {
+            Engine::Remote(address) if address.contains("://") => address.clone(),
+            Engine::Remote(address) => format!("ws://{}", address),
+            Engine::Embedded(path) => format!("rocksdb://{}", path.display()),
+            Engine::Memory => String::from(Storage::MEMORY),
+        }
     }
+}
 
-    parts
-        .iter()
-        .all(|part| !part.is_empty() && part.chars().all(|c| c.is_alphanumeric() || c == '-'))
+#[derive(Debug)]
+enum AddressType {
+    WebAddress,
+    FilePath,
+    Invalid,
 }
 
-fn is_valid_port(port: &str) -> bool {
-    if let Ok(_num) = port.parse::<u16>() {
-        true
-    } else {
-        false
+fn is_valid_host(host: &str) -> bool {
+    // IPv6 addresses are written in brackets, e.g. `[::1]:8000`
+    if let Some(ip) = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+        return ip.parse::<std::net::Ipv6Addr>().is_ok();
     }
+
+    host.split('.')
+        .all(|part| !part.is_empty() && part.chars().all(|c| c.is_alphanumeric() || c == '-'))
 }
 
-fn is_valid_path_segment(segment: &str) -> bool {
-    !segment.is_empty()
-        && segment
-            .chars()
-            .all(|c| c.is_alphanumeric() || c == '.' || c == '_' || c == '-')
+fn is_valid_port(port: &str) -> bool {
+    port.parse::<u16>().is_ok()
 }
 
-fn is_valid_file_path(path: &str) -> bool {
-    if path.is_empty() {
-        return false;
+/// `ws://` and `wss://` URLs and `host:port` are servers, anything else is a path.
+fn identify_address(input: &str) -> AddressType {
+    if input.is_empty() {
+        return AddressType::Invalid;
+    }
+    if input.starts_with("ws://") || input.starts_with("wss://") {
+        return AddressType::WebAddress;
     }
 
-    // Handle special cases
-    if path == "." || path == ".." {
-        return true;
+    match input.rsplit_once(':') {
+        Some((host, port)) if is_valid_host(host) && is_valid_port(port) => AddressType::WebAddress,
+        _ => AddressType::FilePath,
     }
+}
 
-    let parts: Vec<&str> = path.split('/').collect();
+pub async fn create_connection(storage: &Storage) -> surrealdb::Result<Surreal<Any>> {
+    let engine = Engine::from_storage(storage)?;
 
-    // For absolute paths (starting with /)
-    if path.starts_with('/') {
-        return parts[1..].iter().all(|part| {
-            part.is_empty() || // Allow consecutive slashes
-            *part == "." ||
-            *part == ".." ||
-            is_valid_path_segment(part)
-        });
+    if let Engine::Embedded(path) = &engine {
+        // RocksDB only creates the last directory
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(|e| invalid_storage(e.to_string()))?;
+        }
     }
 
-    // For relative paths
-    parts.iter().all(|part| {
-        part.is_empty() || // Allow consecutive slashes
-        *part == "." ||
-        *part == ".." ||
-        is_valid_path_segment(part)
-    })
+    any::connect(engine.endpoint()).await
 }
 
-fn identify_address(input: &str) -> AddressType {
-    // Check if it's a web address (IP:port or domain:port)
-    if let Some((host, port)) = input.rsplit_once(':') {
-        if (is_valid_ip(host) || is_valid_domain(host)) && is_valid_port(port) {
-            return AddressType::WebAddress;
+/// Empty in-memory database with schema defined, e.g. for tests.
+pub async fn memory_db() -> surrealdb::Result<Surreal<Db>> {
+    let db = Surreal::new::<Mem>(()).await?;
+    set_namespace(&db, Storage::DEFAULT_NAMESPACE, Storage::DEFAULT_DATABASE).await?;
+    define_schema(&db).await?;
+
+    Ok(db)
+}
+
+/// Connects to `storage`, signs in to remote server and prepares schema.
+async fn open(storage: &Storage) -> surrealdb::Result<Surreal<Any>> {
+    let db = create_connection(storage).await?;
+
+    // Embedded and in-memory databases have no users to sign in as,
+    // server without password is used anonymously
+    if let Engine::Remote(_) = Engine::from_storage(storage)? {
+        if let Some(password) = password(storage)? {
+            login(&db, storage, &password).await?;
         }
     }
-    // Check if it's a file path
-    else if is_valid_file_path(input) {
-        return AddressType::FilePath;
-    }
+    set_namespace(&db, storage.namespace(), storage.database()).await?;
+    define_schema(&db).await?;
+
+    Ok(db)
+}
 
-    AddressType::Invalid
+fn invalid_storage(message: String) -> surrealdb::Error {
+    surrealdb::Error::Db(surrealdb::error::Db::InvalidModel { message })
 }
 
-pub async fn create_connection(storage: &Storage) -> surrealdb::Result<Surreal<Client>> {
-    let path = storage.path.as_ref().unwrap();
+/// Password from `password`, `password_env` or `password_file`, first one set wins.
+///
+/// Unset variable or unreadable file is an error rather than falling through to
+/// the next source, so a typo doesn't silently connect without credentials.
+fn password(storage: &Storage) -> surrealdb::Result<Option<String>> {
+    password_with(storage, |name| std::env::var(name))
+}
 
-    match identify_address(path) {
-        AddressType::WebAddress => set_remote_db(path).await,
-        AddressType::FilePath => todo!(),
-        AddressType::Invalid => todo!(),
+/// Same as [`password`], environment variables are read with `var`.
+fn password_with(
+    storage: &Storage,
+    var: impl Fn(&str) -> Result<String, std::env::VarError>,
+) -> surrealdb::Result<Option<String>> {
+    if let Some(password) = &storage.password {
+        return Ok(Some(password.clone()));
     }
+    if let Some(name) = &storage.password_env {
+        return var(name)
+            .map(Some)
+            .map_err(|e| invalid_storage(format!("password variable `{}`: {}", name, e)));
+    }
+    if let Some(file) = &storage.password_file {
+        return std::fs::read_to_string(expand_path(file))
+            // Editors and `echo` leave trailing newline
+            .map(|contents| Some(contents.trim_end_matches(['\n', '\r']).to_string()))
+            .map_err(|e| invalid_storage(format!("password file `{}`: {}", file, e)));
+    }
+
+    Ok(None)
 }
 
 async fn login<C: Connection>(
     db: &Surreal<C>,
-    user: &str,
-    pwd: &str,
+    storage: &Storage,
+    password: &str,
 ) -> Result<surrealdb::opt::auth::Jwt, surrealdb::Error> {
-    db.signin(Root {
-        username: user,
-        password: pwd,
-    })
-    .await
+    let username = storage.username.as_deref().unwrap_or("root");
+
+    match storage.auth.unwrap_or_default() {
+        AuthLevel::Root => db.signin(Root { username, password }).await,
+        AuthLevel::Namespace => {
+            db.signin(Namespace {
+                namespace: storage.namespace(),
+                username,
+                password,
+            })
+            .await
+        }
+        AuthLevel::Database => {
+            db.signin(Database {
+                namespace: storage.namespace(),
+                database: storage.database(),
+                username,
+                password,
+            })
+            .await
+        }
+    }
 }
 
+/// Brings schema up to date, see [`migrations`].
 pub async fn define_schema<C: Connection>(db: &Surreal<C>) -> surrealdb::Result<()> {
-    //Define Tag
-    db.query("DEFINE TABLE tag SCHEMAFULL;").await?;
-    db.query("DEFINE FIELD name ON TABLE tag TYPE string;")
-        .await?;
-    //Define Group
-    db.query("DEFINE TABLE group SCHEMAFULL;").await?;
-    db.query("DEFINE FIELD name ON TABLE group TYPE string;")
-        .await?;
-
-    db.query(
-        "DEFINE TABLE tagged TYPE RELATION IN tag OUT host SCHEMAFULL PERMISSIONS NONE;
-
-    -- ------------------------------
-    -- FIELDS
-    -- ------------------------------
-
-    DEFINE FIELD in ON tagged TYPE record<tag> PERMISSIONS FULL;
-    DEFINE FIELD out ON tagged TYPE record<host> PERMISSIONS FULL;",
-    )
-    .await?;
-
-    db.query(
-        "DEFINE TABLE groupped TYPE RELATION IN group OUT host SCHEMAFULL PERMISSIONS NONE;
-
-    -- ------------------------------
-    -- FIELDS
-    -- ------------------------------
-
-    DEFINE FIELD in ON groupped TYPE record<group> PERMISSIONS FULL;
-    DEFINE FIELD out ON groupped TYPE record<host> PERMISSIONS FULL;",
-    )
-    .await?;
-
+    migrations::migrate(db).await?;
     Ok(())
 }
 
-async fn set_namespace<C: Connection>(db: &Surreal<C>) -> Result<(), surrealdb::Error> {
-    db.use_ns("hosts").use_db("hosts").await
+async fn set_namespace<C: Connection>(
+    db: &Surreal<C>,
+    namespace: &str,
+    database: &str,
+) -> Result<(), surrealdb::Error> {
+    db.use_ns(namespace).use_db(database).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn storage(path: &str) -> Storage {
+        Storage {
+            path: Some(path.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_engine_from_storage() {
+        assert_eq!(
+            Engine::from_storage(&storage("127.0.0.1:8000")).unwrap(),
+            Engine::Remote(String::from("127.0.0.1:8000"))
+        );
+
+        let embedded = Engine::from_storage(&storage("/var/lib/sshed/db")).unwrap();
+        assert_eq!(
+            embedded,
+            Engine::Embedded(PathBuf::from("/var/lib/sshed/db"))
+        );
+        assert_eq!(embedded.endpoint(), "rocksdb:///var/lib/sshed/db");
+
+        assert_eq!(
+            Engine::from_storage(&storage(Storage::MEMORY)).unwrap(),
+            Engine::Memory
+        );
+        assert!(Engine::from_storage(&storage("")).is_err());
+    }
+
+    #[test]
+    fn test_remote_addresses() {
+        for address in ["localhost:8000", "db.example.com:8000", "[::1]:8000"] {
+            let engine = Engine::from_storage(&storage(address)).unwrap();
+            assert_eq!(engine.endpoint(), format!("ws://{}", address));
+        }
+
+        let secure = Engine::from_storage(&storage("wss://db.example.com/rpc")).unwrap();
+        assert_eq!(secure.endpoint(), "wss://db.example.com/rpc");
+    }
+
+    #[test]
+    fn test_any_other_address_is_path() {
+        for path in [
+            "/home/me/My Documents/sshed db",
+            "C:\\Users\\me\\sshed\\db",
+            "C:/Users/me/sshed/db",
+            "db:x",
+        ] {
+            assert_eq!(
+                Engine::from_storage(&storage(path)).unwrap(),
+                Engine::Embedded(PathBuf::from(path))
+            );
+        }
+    }
+
+    #[test]
+    fn test_password_sources() {
+        let dir = TempDir::new("password").unwrap();
+        let file = dir.path().join("password");
+        std::fs::write(&file, "from-file\n").unwrap();
+        let var = |name: &str| match name {
+            "DB_PASSWORD" => Ok(String::from("from-env")),
+            _ => Err(std::env::VarError::NotPresent),
+        };
+
+        let mut storage = storage("127.0.0.1:8000");
+        assert_eq!(password_with(&storage, var).unwrap(), None);
+
+        storage.password_file = Some(file.to_string_lossy().to_string());
+        assert_eq!(
+            password_with(&storage, var).unwrap().as_deref(),
+            Some("from-file")
+        );
+
+        storage.password_env = Some(String::from("DB_PASSWORD"));
+        assert_eq!(
+            password_with(&storage, var).unwrap().as_deref(),
+            Some("from-env")
+        );
+
+        storage.password = Some(String::from("inline"));
+        assert_eq!(
+            password_with(&storage, var).unwrap().as_deref(),
+            Some("inline")
+        );
+
+        storage.password = None;
+        storage.password_env = Some(String::from("DB_PASSWORD_UNSET"));
+        assert!(password_with(&storage, var).is_err());
+    }
 }
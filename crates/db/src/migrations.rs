@@ -0,0 +1,172 @@
+use surrealdb::{Connection, Surreal};
+
+use crate::invalid_storage;
+
+/// Step of schema, applied once and recorded in `meta:schema`.
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    /// Schema version after this migration, one more than the previous one.
+    pub version: u32,
+    pub description: &'static str,
+    pub statements: &'static str,
+}
+
+/// Every migration in order of versions, existing ones must never change.
+///
+/// Databases created before migrations existed already have the tables of the
+/// first migration, so its statements must not fail when they exist.
+pub const MIGRATIONS: &[Migration] = &[Migration {
+    version: 1,
+    description: "tags, groups and their relations to hosts",
+    statements: "
+    DEFINE TABLE IF NOT EXISTS meta SCHEMALESS;
+
+    DEFINE TABLE IF NOT EXISTS tag SCHEMAFULL;
+    DEFINE FIELD IF NOT EXISTS name ON TABLE tag TYPE string;
+
+    DEFINE TABLE IF NOT EXISTS group SCHEMAFULL;
+    DEFINE FIELD IF NOT EXISTS name ON TABLE group TYPE string;
+
+    DEFINE TABLE IF NOT EXISTS tagged TYPE RELATION IN tag OUT host SCHEMAFULL PERMISSIONS NONE;
+    DEFINE FIELD IF NOT EXISTS in ON tagged TYPE record<tag> PERMISSIONS FULL;
+    DEFINE FIELD IF NOT EXISTS out ON tagged TYPE record<host> PERMISSIONS FULL;
+
+    DEFINE TABLE IF NOT EXISTS groupped TYPE RELATION IN group OUT host SCHEMAFULL PERMISSIONS NONE;
+    DEFINE FIELD IF NOT EXISTS in ON groupped TYPE record<group> PERMISSIONS FULL;
+    DEFINE FIELD IF NOT EXISTS out ON groupped TYPE record<host> PERMISSIONS FULL;
+
+    DEFINE TABLE IF NOT EXISTS subgroup TYPE RELATION IN group OUT group SCHEMAFULL PERMISSIONS NONE;
+    DEFINE FIELD IF NOT EXISTS in ON subgroup TYPE record<group> PERMISSIONS FULL;
+    DEFINE FIELD IF NOT EXISTS out ON subgroup TYPE record<group> PERMISSIONS FULL;",
+}];
+
+/// Schema version this build of sshed works with.
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or_default()
+}
+
+/// Version recorded in the database, 0 when no migration was applied yet.
+pub async fn schema_version<C: Connection>(db: &Surreal<C>) -> surrealdb::Result<u32> {
+    let mut response = db
+        .query("SELECT VALUE version FROM ONLY meta:schema")
+        .await?
+        .check()?;
+    let version: Option<u32> = response.take(0)?;
+
+    Ok(version.unwrap_or_default())
+}
+
+/// Applies migrations newer than the recorded version, returns the new version.
+pub async fn migrate<C: Connection>(db: &Surreal<C>) -> surrealdb::Result<u32> {
+    apply(db, MIGRATIONS).await
+}
+
+/// Applies pending `migrations`, each one in its own transaction together with
+/// the version record, so failed migration leaves database at previous version.
+///
+/// Database migrated by newer sshed is refused, its schema may not be readable.
+async fn apply<C: Connection>(db: &Surreal<C>, migrations: &[Migration]) -> surrealdb::Result<u32> {
+    let latest = migrations.last().map(|m| m.version).unwrap_or_default();
+    let mut version = schema_version(db).await?;
+
+    if version > latest {
+        return Err(invalid_storage(format!(
+            "database schema version {} is newer than {} supported by this sshed, upgrade sshed",
+            version, latest
+        )));
+    }
+
+    for migration in migrations.iter().filter(|m| m.version > version) {
+        db.query("BEGIN TRANSACTION;")
+            .query(migration.statements)
+            .query("UPSERT meta:schema SET version = $version;")
+            .query("COMMIT TRANSACTION;")
+            .bind(("version", migration.version))
+            .await?
+            .check()
+            .map_err(|e| {
+                invalid_storage(format!(
+                    "migration {} ({}) failed: {}",
+                    migration.version, migration.description, e
+                ))
+            })?;
+        version = migration.version;
+    }
+
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use surrealdb::engine::local::Mem;
+
+    async fn empty_db() -> Surreal<surrealdb::engine::local::Db> {
+        let db = Surreal::new::<Mem>(()).await.unwrap();
+        db.use_ns("test").use_db("test").await.unwrap();
+        db
+    }
+
+    #[test]
+    fn test_versions_are_ordered() {
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, index as u32 + 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_migrate_keeps_data() {
+        let db = empty_db().await;
+        assert_eq!(schema_version(&db).await.unwrap(), 0);
+
+        assert_eq!(migrate(&db).await.unwrap(), latest_version());
+        db.query("CREATE tag:prod SET name = 'prod'").await.unwrap();
+
+        // Already migrated database is left as it is
+        assert_eq!(migrate(&db).await.unwrap(), latest_version());
+        let mut response = db.query("SELECT VALUE name FROM tag").await.unwrap();
+        let names: Vec<String> = response.take(0).unwrap();
+        assert_eq!(names, ["prod"]);
+
+        let next = Migration {
+            version: latest_version() + 1,
+            description: "tag colors",
+            statements: "DEFINE FIELD color ON TABLE tag TYPE option<string>;",
+        };
+        let migrations = [MIGRATIONS, &[next]].concat();
+        assert_eq!(apply(&db, &migrations).await.unwrap(), next.version);
+    }
+
+    #[tokio::test]
+    async fn test_failed_migration_is_rolled_back() {
+        let db = empty_db().await;
+        migrate(&db).await.unwrap();
+
+        let broken = Migration {
+            version: latest_version() + 1,
+            description: "broken",
+            statements: "DEFINE TABLE host_v2 SCHEMAFULL; THROW 'broken';",
+        };
+        let migrations = [MIGRATIONS, &[broken]].concat();
+        assert!(apply(&db, &migrations).await.is_err());
+        assert_eq!(schema_version(&db).await.unwrap(), latest_version());
+
+        let mut response = db.query("INFO FOR DB").await.unwrap();
+        let tables: Option<BTreeMap<String, String>> = response.take("tables").unwrap();
+        let tables = tables.unwrap();
+        assert!(tables.contains_key("tag"));
+        assert!(!tables.contains_key("host_v2"));
+    }
+
+    #[tokio::test]
+    async fn test_newer_schema_is_refused() {
+        let db = empty_db().await;
+        db.query("UPSERT meta:schema SET version = $version")
+            .bind(("version", latest_version() + 1))
+            .await
+            .unwrap();
+
+        assert!(migrate(&db).await.is_err());
+    }
+}
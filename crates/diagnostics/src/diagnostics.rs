@@ -0,0 +1,147 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Part of the file was ignored.
+    Error,
+    /// File was read, but probably not the way its author intended.
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Problem found while reading a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub path: PathBuf,
+    /// 1-based line, 0 when problem concerns the whole file.
+    pub line: usize,
+    /// 1-based column, 0 when problem concerns the whole line.
+    pub column: usize,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn error(path: &Path, line: usize, column: usize, message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, path, line, column, message)
+    }
+
+    pub fn warning(path: &Path, line: usize, column: usize, message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, path, line, column, message)
+    }
+
+    fn new(
+        severity: Severity,
+        path: &Path,
+        line: usize,
+        column: usize,
+        message: impl Into<String>,
+    ) -> Self {
+        Self {
+            severity,
+            path: path.to_path_buf(),
+            line,
+            column,
+            message: message.into(),
+        }
+    }
+
+    /// Location as `path:line:column`, parts which aren't known are left out.
+    pub fn location(&self) -> String {
+        match (self.line, self.column) {
+            (0, _) => format!("{}", self.path.display()),
+            (line, 0) => format!("{}:{}", self.path.display(), line),
+            (line, column) => format!("{}:{}:{}", self.path.display(), line, column),
+        }
+    }
+
+    /// Renders diagnostic in compiler style, together with the offending line of `source`.
+    ///
+    /// ```text
+    /// warning: unknown directive `Hostnme`
+    ///   --> ~/.ssh/config:3:5
+    ///    |
+    ///  3 |     Hostnme 10.0.0.1
+    ///    |     ^
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        let mut out = self.to_string();
+
+        let Some(line) = self.line.checked_sub(1).and_then(|n| source.lines().nth(n)) else {
+            return out;
+        };
+
+        let number = self.line.to_string();
+        let gutter = " ".repeat(number.len());
+        out.push_str(&format!("\n{} |\n{} | {}", gutter, number, line));
+        if self.column > 0 {
+            out.push_str(&format!("\n{} | {}^", gutter, " ".repeat(self.column - 1)));
+        }
+
+        out
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {}\n  --> {}",
+            self.severity,
+            self.message,
+            self.location()
+        )
+    }
+}
+
+/// At least one of `diagnostics` is an error.
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(|d| d.severity == Severity::Error)
+}
+
+/// 1-based line and column of byte `offset` in `source`.
+pub fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let diagnostic =
+            Diagnostic::warning(Path::new("config"), 2, 5, "unknown directive `Hostnme`");
+
+        assert_eq!(
+            diagnostic.render("Host a\n    Hostnme 10.0.0.1\n"),
+            "warning: unknown directive `Hostnme`\n  --> config:2:5\n  |\n2 |     Hostnme 10.0.0.1\n  |     ^"
+        );
+        assert_eq!(
+            Diagnostic::error(Path::new("config"), 0, 0, "unreadable").to_string(),
+            "error: unreadable\n  --> config"
+        );
+    }
+
+    #[test]
+    fn test_line_column() {
+        let source = "a = 1\nbb = [\n";
+        assert_eq!(line_column(source, 0), (1, 1));
+        assert_eq!(line_column(source, 9), (2, 4));
+    }
+}
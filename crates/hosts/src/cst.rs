@@ -0,0 +1,567 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::Result,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    host::{
+        writer::{self, Metadata},
+        Host,
+    },
+    include::IncludeResolver,
+    tokenizer::{self, Directive, Stanza, StanzaKind},
+};
+
+/// Lines of a stanza in its file, 1-based and inclusive, metadata comments included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Finds `Host` stanza by one of its aliases, wildcard stanzas by all their patterns.
+fn find_stanza<'a>(stanzas: &'a [Stanza], name: &str) -> Option<&'a Stanza> {
+    stanzas.iter().find(|stanza| {
+        stanza.kind == StanzaKind::Host
+            && (stanza.patterns().join(" ") == name
+                || stanza
+                    .patterns()
+                    .iter()
+                    .any(|p| p.eq_ignore_ascii_case(name)))
+    })
+}
+
+/// Finds `Host` stanza defining `host` in already tokenized `stanzas`.
+pub(crate) fn find_host_stanza<'a>(stanzas: &'a [Stanza], host: &Host) -> Option<&'a Stanza> {
+    host.aliases
+        .iter()
+        .chain(std::iter::once(&host.name))
+        .find_map(|name| find_stanza(stanzas, name))
+}
+
+/// ssh config file kept exactly as written, so it can be edited without reformatting.
+///
+/// Only lines touched by an edit are rewritten, everything else, including comments,
+/// indentation, blank lines and directives sshed doesn't understand, stays as it was.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigDocument {
+    lines: Vec<String>,
+    line_ending: &'static str,
+    trailing_newline: bool,
+}
+
+impl ConfigDocument {
+    pub fn parse(content: &str) -> Self {
+        let line_ending = if content.contains("\r\n") {
+            "\r\n"
+        } else {
+            "\n"
+        };
+        let trailing_newline = content.ends_with('\n');
+
+        let mut lines: Vec<String> = content
+            .split('\n')
+            .map(|line| line.strip_suffix('\r').unwrap_or(line).to_string())
+            .collect();
+        if trailing_newline || content.is_empty() {
+            lines.pop();
+        }
+
+        Self {
+            lines,
+            line_ending,
+            trailing_newline,
+        }
+    }
+
+    pub fn stanzas(&self) -> Vec<Stanza> {
+        tokenizer::tokenize(&self.to_string())
+    }
+
+    /// Span of the `Host` stanza defining `host`.
+    pub fn span_of(&self, host: &Host) -> Option<Span> {
+        self.find_host(host).map(|stanza| Span {
+            start: stanza.start,
+            end: stanza.end,
+        })
+    }
+
+    fn find(&self, name: &str) -> Option<Stanza> {
+        find_stanza(&self.stanzas(), name).cloned()
+    }
+
+    fn find_host(&self, host: &Host) -> Option<Stanza> {
+        find_host_stanza(&self.stanzas(), host).cloned()
+    }
+
+    /// Replaces patterns of stanza `old` with `new`, keeping the other patterns.
+    pub fn rename_host(&mut self, old: &str, new: &str) -> bool {
+        let Some(header) = self.find(old).and_then(|s| s.header) else {
+            return false;
+        };
+
+        let args: Vec<String> = if header.args.join(" ") == old {
+            vec![new.to_string()]
+        } else {
+            header
+                .args
+                .iter()
+                .map(|arg| {
+                    if arg.eq_ignore_ascii_case(old) {
+                        new.to_string()
+                    } else {
+                        arg.clone()
+                    }
+                })
+                .collect()
+        };
+
+        self.replace_args(header.line, &args);
+        true
+    }
+
+    /// Removes stanza `name` together with its metadata comments.
+    pub fn delete_host(&mut self, name: &str) -> bool {
+        let Some(stanza) = self.find(name) else {
+            return false;
+        };
+
+        let (mut start, mut end) = (stanza.start - 1, stanza.end);
+        // Don't leave two blank lines where the stanza used to be
+        let blank_before = start == 0 || self.lines[start - 1].trim().is_empty();
+        if blank_before && self.lines.get(end).is_some_and(|l| l.trim().is_empty()) {
+            end += 1;
+        }
+        // Nor a trailing blank line when it was the last one
+        if end >= self.lines.len() && start > 0 && self.lines[start - 1].trim().is_empty() {
+            start -= 1;
+        }
+
+        self.lines.drain(start..end);
+        true
+    }
+
+    /// Brings stanza `name` in line with `host`.
+    ///
+    /// Options whose value didn't change are left untouched, changed ones are rewritten
+    /// in place, removed ones are dropped and new ones are added after the last option
+    /// of the stanza. Metadata and option comments are only rewritten when they differ.
+    pub fn update_host(&mut self, name: &str, host: &Host, metadata: &Metadata) -> bool {
+        let Some(stanza) = self.find(name) else {
+            return false;
+        };
+        let Some(header) = stanza.header.clone() else {
+            return false;
+        };
+
+        let mut replaced: HashMap<usize, String> = HashMap::new();
+        let mut inserted: HashMap<usize, Vec<String>> = HashMap::new();
+        let mut before: HashMap<usize, Vec<String>> = HashMap::new();
+        let mut removed: Vec<usize> = vec![];
+        let mut uncommented: Vec<usize> = vec![];
+
+        let patterns = writer::header(host).args;
+        if patterns != header.args {
+            replaced.insert(header.line, self.with_args(header.line, &patterns));
+        }
+
+        // Metadata written by sshed, other comments above the stanza are kept
+        let desired = writer::metadata_lines(metadata);
+        let existing: Vec<usize> = (stanza.start..header.line)
+            .filter(|n| is_metadata(&self.lines[n - 1]))
+            .collect();
+        let current: Vec<String> = existing
+            .iter()
+            .map(|n| self.lines[n - 1].trim().to_string())
+            .collect();
+        let metadata_lines = if current != desired {
+            removed.extend(&existing);
+            desired
+        } else {
+            vec![]
+        };
+
+        let options = writer::options(host);
+        let indent = stanza
+            .directives
+            .first()
+            .map(|d| indentation(&self.lines[d.line - 1]).to_string())
+            .unwrap_or_else(|| String::from("    "));
+
+        let mut keywords: Vec<String> = vec![];
+        for directive in stanza.directives.iter().chain(&options) {
+            let keyword = directive.keyword.to_lowercase();
+            if !keywords.contains(&keyword) && keyword != "include" {
+                keywords.push(keyword);
+            }
+        }
+
+        let comments = stanza.option_comments();
+        for keyword in keywords {
+            let current: Vec<&Directive> = stanza
+                .directives
+                .iter()
+                .filter(|d| d.is(&keyword))
+                .collect();
+            let wanted: Vec<&Directive> = options.iter().filter(|d| d.is(&keyword)).collect();
+
+            // Comment is moved above the first option when it changed
+            let comment = metadata.option_comments.get(&keyword);
+            let comment_lines: Vec<String> = comment
+                .map(|c| writer::comment_lines(c))
+                .unwrap_or_default()
+                .into_iter()
+                .map(|line| format!("{}{}", indent, line))
+                .collect();
+            let keep_comment = comments.get(&keyword) == comment;
+            if !keep_comment {
+                for c in &current {
+                    removed.extend(self.comments_above(c.line, header.line));
+                    uncommented.push(c.line);
+                }
+            }
+
+            for i in 0..current.len().max(wanted.len()) {
+                if i == 0 && !keep_comment && !current.is_empty() && !wanted.is_empty() {
+                    before.insert(current[0].line, comment_lines.clone());
+                }
+
+                match (current.get(i), wanted.get(i)) {
+                    (Some(c), Some(w)) if c.args == w.args => {}
+                    (Some(c), Some(w)) => {
+                        replaced.insert(c.line, self.with_args(c.line, &w.args));
+                    }
+                    (Some(c), None) => removed.push(c.line),
+                    (None, Some(w)) => {
+                        let at = current.last().map(|c| c.line).unwrap_or(stanza.end);
+                        let lines = inserted.entry(at).or_default();
+                        if i == 0 {
+                            lines.extend(comment_lines.iter().cloned());
+                        }
+                        lines.push(format!("{}{}", indent, w));
+                    }
+                    (None, None) => {}
+                }
+            }
+        }
+
+        for number in uncommented {
+            let line = replaced.get(&number).unwrap_or(&self.lines[number - 1]);
+            let line = tokenizer::split_comment(line).0.trim_end().to_string();
+            replaced.insert(number, line);
+        }
+
+        let mut lines = vec![];
+        for (index, line) in self.lines.iter().enumerate() {
+            let number = index + 1;
+            if number == header.line {
+                lines.extend(metadata_lines.iter().cloned());
+            }
+            if !removed.contains(&number) {
+                lines.extend(before.remove(&number).into_iter().flatten());
+                match replaced.get(&number) {
+                    Some(new) => lines.push(new.clone()),
+                    None => lines.push(line.clone()),
+                }
+            }
+            lines.extend(inserted.remove(&number).into_iter().flatten());
+        }
+        self.lines = lines;
+
+        true
+    }
+
+    /// Comment lines directly above option on line `number`, below `header` line.
+    fn comments_above(&self, number: usize, header: usize) -> Vec<usize> {
+        (header + 1..number)
+            .rev()
+            .take_while(|n| self.lines[n - 1].trim().starts_with('#'))
+            .collect()
+    }
+
+    /// Appends new stanza for `host` at the end of the file.
+    pub fn append_host(&mut self, host: &Host, metadata: &Metadata) {
+        if self.lines.last().is_some_and(|l| !l.trim().is_empty()) {
+            self.lines.push(String::new());
+        }
+        self.lines
+            .extend(writer::write_host(host, metadata).lines().map(String::from));
+        self.trailing_newline = true;
+    }
+
+    /// Line `number` with its arguments replaced.
+    ///
+    /// Keyword, indentation, separator and trailing comment are kept as written.
+    fn with_args(&self, number: usize, args: &[String]) -> String {
+        let raw = &self.lines[number - 1];
+        let (body, comment) = tokenizer::split_comment(raw);
+        let content = body.trim_start();
+        let indent = indentation(raw);
+        let gap = if comment.is_empty() {
+            ""
+        } else {
+            &content[content.trim_end().len()..]
+        };
+        let content = content.trim_end();
+
+        let keyword_end = content
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(content.len());
+        let rest = &content[keyword_end..];
+        let separator = &rest[..rest.len() - rest.trim_start_matches([' ', '\t', '=']).len()];
+        let separator = if separator.is_empty() { " " } else { separator };
+
+        // Directive without keyword renders just its quoted arguments
+        let args = Directive {
+            keyword: String::new(),
+            args: args.to_vec(),
+            line: number,
+            comment: None,
+        }
+        .to_string();
+
+        format!(
+            "{}{}{}{}{}{}",
+            indent,
+            &content[..keyword_end],
+            separator,
+            args.trim_start(),
+            gap,
+            comment
+        )
+    }
+
+    fn replace_args(&mut self, number: usize, args: &[String]) {
+        self.lines[number - 1] = self.with_args(number, args);
+    }
+}
+
+impl std::fmt::Display for ConfigDocument {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.lines.join(self.line_ending))?;
+        if self.trailing_newline && !self.lines.is_empty() {
+            write!(f, "{}", self.line_ending)?;
+        }
+        Ok(())
+    }
+}
+
+/// Every ssh config file pulled in from a root config, editable in place.
+#[derive(Debug, Default)]
+pub struct ConfigFiles {
+    files: Vec<ConfigFile>,
+}
+
+#[derive(Debug)]
+struct ConfigFile {
+    path: PathBuf,
+    document: ConfigDocument,
+    modified: bool,
+}
+
+impl ConfigFiles {
+    /// Reads config at `path` together with every file its `Include` directives pull in.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        Self::load_with(&IncludeResolver::new(), path)
+    }
+
+    pub fn load_with(includes: &IncludeResolver, path: PathBuf) -> Result<Self> {
+        let files = includes
+            .collect(path, None, &mut vec![])?
+            .into_iter()
+            .map(|source| ConfigFile {
+                path: source.path,
+                document: ConfigDocument::parse(&source.content),
+                modified: false,
+            })
+            .collect();
+
+        Ok(Self { files })
+    }
+
+    /// File and lines where `host` is defined.
+    pub fn locate(&self, host: &Host) -> Option<(&Path, Span)> {
+        self.files
+            .iter()
+            .find_map(|file| Some((file.path.as_path(), file.document.span_of(host)?)))
+    }
+
+    pub fn document(&self, path: &Path) -> Option<&ConfigDocument> {
+        self.files
+            .iter()
+            .find(|file| file.path == path)
+            .map(|file| &file.document)
+    }
+
+    /// Updates stanza of `name` to match `host`, see [`ConfigDocument::update_host`].
+    pub fn update_host(&mut self, name: &str, host: &Host, metadata: &Metadata) -> bool {
+        self.edit(|document| document.update_host(name, host, metadata))
+    }
+
+    pub fn rename_host(&mut self, old: &str, new: &str) -> bool {
+        self.edit(|document| document.rename_host(old, new))
+    }
+
+    pub fn delete_host(&mut self, name: &str) -> bool {
+        self.edit(|document| document.delete_host(name))
+    }
+
+    /// Applies `edit` to the first file where it succeeds.
+    fn edit(&mut self, mut edit: impl FnMut(&mut ConfigDocument) -> bool) -> bool {
+        for file in &mut self.files {
+            if edit(&mut file.document) {
+                file.modified = true;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Writes back files which were edited.
+    pub fn save(&mut self) -> Result<()> {
+        for file in self.files.iter_mut().filter(|file| file.modified) {
+            fs::write(&file.path, file.document.to_string())?;
+            file.modified = false;
+        }
+        Ok(())
+    }
+}
+
+fn is_metadata(line: &str) -> bool {
+    let line = line.trim();
+    line.starts_with("#--(")
+        || line.starts_with("#--[")
+        || line.starts_with("#--{")
+        || line.starts_with("# ")
+        || line == "#"
+}
+
+fn indentation(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::pattern::HostPattern;
+    use std::collections::BTreeMap;
+
+    const CONFIG: &str = "\
+# Personal servers
+Include config.d/*
+
+#--(servers)
+# Development server
+Host dev dev-alt
+\tHostName 10.0.0.1
+\tUser=me   # shared account
+
+\t# keys
+\tIdentityFile ~/.ssh/id_dev
+\tForwardX11 yes
+
+Host prod
+    HostName 10.0.0.2
+";
+
+    fn dev() -> Host {
+        Host {
+            name: String::from("dev"),
+            patterns: vec![HostPattern::new("dev"), HostPattern::new("dev-alt")],
+            aliases: vec![String::from("dev"), String::from("dev-alt")],
+            host_name: Some(String::from("10.0.0.1")),
+            user: Some(String::from("me")),
+            identity_file: Some(vec![PathBuf::from("~/.ssh/id_dev")]),
+            ignored_fields: HashMap::from([(
+                String::from("ForwardX11"),
+                vec![String::from("yes")],
+            )]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_round_trip_and_span() {
+        let crlf = CONFIG.replace('\n', "\r\n");
+        assert_eq!(ConfigDocument::parse(CONFIG).to_string(), CONFIG);
+        assert_eq!(ConfigDocument::parse(&crlf).to_string(), crlf);
+        assert_eq!(
+            ConfigDocument::parse(CONFIG).span_of(&dev()),
+            Some(Span { start: 4, end: 12 })
+        );
+    }
+
+    #[test]
+    fn test_update_only_touches_changed_lines() {
+        let mut document = ConfigDocument::parse(CONFIG);
+        let mut metadata = Metadata {
+            comment: Some(String::from("Development server")),
+            groups: vec![String::from("servers")],
+            option_comments: BTreeMap::from([
+                (String::from("user"), String::from("shared account")),
+                (String::from("identityfile"), String::from("keys")),
+            ]),
+            ..Default::default()
+        };
+
+        assert!(document.update_host("dev", &dev(), &metadata));
+        assert_eq!(document.to_string(), CONFIG);
+
+        let mut host = dev();
+        host.user = Some(String::from("admin"));
+        host.port = Some(2222);
+        host.identity_file = Some(vec![
+            PathBuf::from("~/.ssh/id_dev"),
+            PathBuf::from("~/.ssh/id ed"),
+        ]);
+        host.ignored_fields.clear();
+        metadata.tags = vec![String::from("abc")];
+
+        assert!(document.update_host("dev", &host, &metadata));
+        assert_eq!(
+            document.to_string(),
+            CONFIG
+                .replace("User=me ", "User=admin ")
+                .replace("#--(servers)\n", "#--(servers)\n#--[abc]\n")
+                .replace(
+                    "id_dev\n\tForwardX11 yes\n",
+                    "id_dev\n\tIdentityFile \"~/.ssh/id ed\"\n\tPort 2222\n"
+                )
+        );
+
+        metadata.option_comments = BTreeMap::from([
+            (String::from("user"), String::from("admin account")),
+            (String::from("port"), String::from("non-standard")),
+        ]);
+
+        assert!(document.update_host("dev", &host, &metadata));
+        assert_eq!(
+            document.to_string(),
+            CONFIG
+                .replace("\tUser=me   # shared account\n", "\t# admin account\n\tUser=admin\n")
+                .replace("#--(servers)\n", "#--(servers)\n#--[abc]\n")
+                .replace(
+                    "\t# keys\n\tIdentityFile ~/.ssh/id_dev\n\tForwardX11 yes\n",
+                    "\tIdentityFile ~/.ssh/id_dev\n\tIdentityFile \"~/.ssh/id ed\"\n\t# non-standard\n\tPort 2222\n"
+                )
+        );
+    }
+
+    #[test]
+    fn test_rename_and_delete() {
+        let mut document = ConfigDocument::parse(CONFIG);
+
+        assert!(document.rename_host("dev-alt", "dev2"));
+        assert!(document.to_string().contains("Host dev dev2\n"));
+        assert!(!document.rename_host("missing", "x"));
+
+        assert!(document.delete_host("prod"));
+        assert!(document.delete_host("dev"));
+        assert_eq!(
+            document.to_string(),
+            "# Personal servers\nInclude config.d/*\n"
+        );
+    }
+}
@@ -1,15 +1,35 @@
+pub mod attribute;
+pub mod pattern;
+pub mod source;
 pub mod table;
-use std::{collections::HashMap, ops::Index, path::PathBuf, time::Duration};
-
+pub mod writer;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    time::Duration,
+};
+
+use crate::cst::ConfigFiles;
+use attribute::Attributes;
+use config::expand::{ExpandedPath, Tokens};
+use pattern::HostPattern;
 use serde::{Deserialize, Serialize};
+use source::HostSource;
 use ssh2_config::HostParams;
 use surrealdb::{sql::Thing, Connection, Error, Response, Surreal};
 use table::{Group, Tag};
+use writer::Metadata;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct Host {
     /// Hosts name in file.
     pub name: String,
+    /// Every pattern from `Host` line, including negated ones.
+    #[serde(default)]
+    pub patterns: Vec<HostPattern>,
+    /// Concrete names from `Host` line, all of them can be used with `ssh <alias>`.
+    #[serde(default)]
+    pub aliases: Vec<String>,
     /// Specifies to use the specified address on the local machine as the source address of the connection
     pub bind_address: Option<String>,
     /// Use the specified address on the local machine as the source address of the connection
@@ -64,10 +84,58 @@ pub struct Host {
     pub unsupported_fields: HashMap<String, Vec<String>>,
 }
 
+impl Host {
+    /// Host consists only of wildcard or negated patterns, e.g. `Host *` or `Host *.prod !bastion.prod`.
+    pub fn is_wildcard(&self) -> bool {
+        self.aliases.is_empty()
+    }
+
+    /// Checks whether `name` is matched by this host's patterns.
+    pub fn matches(&self, name: &str) -> bool {
+        pattern::matches_list(&self.patterns, name)
+    }
+
+    /// Values of `%h`, `%r` and `%p` tokens when connecting to this host.
+    pub fn tokens(&self) -> Tokens {
+        Tokens::remote(
+            Some(self.host_name.clone().unwrap_or_else(|| self.name.clone())),
+            self.user.clone(),
+            self.port,
+        )
+    }
+
+    /// Identity files as written in config and expanded.
+    pub fn identity_files(&self) -> Vec<ExpandedPath> {
+        let tokens = self.tokens();
+        self.identity_file
+            .iter()
+            .flatten()
+            .map(|file| ExpandedPath::new(&file.to_string_lossy(), &tokens))
+            .collect()
+    }
+
+    /// Certificate file as written in config and expanded.
+    pub fn certificate(&self) -> Option<ExpandedPath> {
+        self.certificate_file
+            .as_ref()
+            .map(|file| ExpandedPath::new(&file.to_string_lossy(), &self.tokens()))
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EnhancedHost {
     pub host: Host,
+    /// Description from `# ` lines above `Host` line, one line each.
     pub comment: Option<String>,
+    /// File and lines the host was read from.
+    #[serde(default)]
+    pub source: Option<HostSource>,
+    /// Custom attributes from `#--{key=value}` comment.
+    #[serde(default)]
+    pub attributes: Attributes,
+    /// Comments written above options, keyed by lowercase keyword.
+    #[serde(default)]
+    pub option_comments: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -75,6 +143,12 @@ pub struct HostRecord {
     pub id: Thing,
     pub host: Host,
     pub comment: Option<String>,
+    #[serde(default)]
+    pub source: Option<HostSource>,
+    #[serde(default)]
+    pub attributes: Attributes,
+    #[serde(default)]
+    pub option_comments: BTreeMap<String, String>,
 }
 
 impl PartialEq for HostRecord {
@@ -84,6 +158,81 @@ impl PartialEq for HostRecord {
 }
 
 impl EnhancedHost {
+    /// Renders host as ssh config `Host` stanza with sshed metadata comments.
+    pub fn to_ssh_config(&self, groups: &[String], tags: &[String]) -> String {
+        let metadata = Metadata {
+            comment: self.comment.clone(),
+            groups: groups.to_vec(),
+            tags: tags.to_vec(),
+            attributes: self.attributes.clone(),
+            option_comments: self.option_comments.clone(),
+        };
+
+        writer::write_host(&self.host, &metadata)
+    }
+
+    /// Renders stored host together with its groups and tags.
+    pub async fn render<C: Connection>(
+        db: &Surreal<C>,
+        record: &HostRecord,
+    ) -> Result<String, Error> {
+        let metadata = Self::metadata(db, record).await?;
+
+        Ok(writer::write_host(&record.host, &metadata))
+    }
+
+    /// Patches stanza `name` in ingested config files so it matches stored host.
+    ///
+    /// Returns `false` when no file defines `name`. Files are only changed in memory,
+    /// call [`ConfigFiles::save`] to write them. Hosts of read-only sources are refused.
+    pub async fn write_back<C: Connection>(
+        db: &Surreal<C>,
+        record: &HostRecord,
+        name: &str,
+        files: &mut ConfigFiles,
+    ) -> Result<bool, Error> {
+        if let Some(source) = record.source.as_ref().filter(|s| s.read_only) {
+            return Err(Error::Db(surrealdb::error::Db::InvalidModel {
+                message: format!(
+                    "host `{}` comes from read-only source `{}`",
+                    name,
+                    source.origin.as_deref().unwrap_or_default()
+                ),
+            }));
+        }
+
+        let metadata = Self::metadata(db, record).await?;
+
+        Ok(files.update_host(name, &record.host, &metadata))
+    }
+
+    /// Comment, attributes and sorted names of groups and tags of stored host.
+    async fn metadata<C: Connection>(
+        db: &Surreal<C>,
+        record: &HostRecord,
+    ) -> Result<Metadata, Error> {
+        let mut groups: Vec<String> = Self::get_groups(db, &record.id)
+            .await?
+            .into_values()
+            .map(|g| g.name)
+            .collect();
+        let mut tags: Vec<String> = Self::get_tags(db, &record.id)
+            .await?
+            .into_values()
+            .map(|t| t.name)
+            .collect();
+        groups.sort();
+        tags.sort();
+
+        Ok(Metadata {
+            comment: record.comment.clone(),
+            groups,
+            tags,
+            attributes: record.attributes.clone(),
+            option_comments: record.option_comments.clone(),
+        })
+    }
+
     pub async fn create<C: Connection>(
         db: &Surreal<C>,
         data: EnhancedHost,
@@ -106,6 +255,11 @@ impl EnhancedHost {
         Ok(record_id)
     }
 
+    pub async fn get_all<C: Connection>(db: &Surreal<C>) -> surrealdb::Result<Vec<HostRecord>> {
+        db.select("host").await
+    }
+
+    /// Host stored under `name`, the record `create` and `update` write to.
     pub async fn get_host_by_name<C: Connection>(
         db: &Surreal<C>,
         name: String,
@@ -128,6 +282,24 @@ impl EnhancedHost {
         }
     }
 
+    /// Host which can be connected to as `alias`, either its name or any other
+    /// concrete pattern of its `Host` line. Only for lookups, never to find a
+    /// record to write, several hosts may share an alias.
+    pub async fn get_host_by_alias<C: Connection>(
+        db: &Surreal<C>,
+        alias: String,
+    ) -> surrealdb::Result<Option<HostRecord>> {
+        // Host named `alias` wins over other hosts which only list it
+        if let Some(record) = Self::get_host_by_name(db, alias.clone()).await? {
+            return Ok(Some(record));
+        }
+
+        db.query("SELECT * FROM host WHERE (host.aliases ?? []) CONTAINS $alias LIMIT 1")
+            .bind(("alias", alias))
+            .await?
+            .take(0)
+    }
+
     pub async fn update<C: Connection>(db: &Surreal<C>, data: Self) -> Result<HostRecord, Error> {
         let record = match Self::get_host_by_name(&db, data.host.name.clone()).await {
             Ok(t) => match t {
@@ -169,6 +341,18 @@ impl EnhancedHost {
         }
     }
 
+    /// Deletes host together with its `tagged` and `groupped` relations.
+    pub async fn delete<C: Connection>(db: &Surreal<C>, host_id: &Thing) -> Result<(), Error> {
+        db.query(
+            "DELETE tagged WHERE out = $host; DELETE groupped WHERE out = $host; DELETE $host",
+        )
+        .bind(("host", host_id.clone()))
+        .await?
+        .check()?;
+
+        Ok(())
+    }
+
     pub async fn add_tag<C: Connection>(
         db: &Surreal<C>,
         host_id: &Thing,
@@ -280,13 +464,106 @@ impl EnhancedHost {
     }
 }
 
+/// Host stanza which doesn't name any concrete host, such as `Host *`.
+///
+/// Wildcard stanzas only provide defaults for other hosts, so they are stored
+/// in their own table and never show up between regular hosts.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WildcardHost {
+    pub host: Host,
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub source: Option<HostSource>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WildcardRecord {
+    pub id: Thing,
+    pub host: Host,
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub source: Option<HostSource>,
+}
+
+impl WildcardHost {
+    /// Stores wildcard stanza, the same patterns in another file or at another
+    /// place are a stanza of their own.
+    pub async fn create_or_update<C: Connection>(
+        db: &Surreal<C>,
+        data: WildcardHost,
+    ) -> surrealdb::Result<Thing> {
+        let name = data.host.name.clone();
+        let source = data.source.clone();
+
+        if let Some(record) = Self::find(db, &name, source.as_ref()).await? {
+            let _: Option<WildcardHost> = db
+                .update(("wildcard", &record.id.id.to_string()))
+                .content(data)
+                .await?;
+            return Ok(record.id);
+        }
+
+        let _: Option<WildcardHost> = db.create("wildcard").content(data).await?;
+
+        Self::find(db, &name, source.as_ref())
+            .await?
+            .map(|r| r.id)
+            .ok_or(Error::Db(surrealdb::error::Db::InvalidModel {
+                message: String::from("Failed to create new wildcard host"),
+            }))
+    }
+
+    /// Wildcard stanza `name` which starts at the same line of the same file.
+    async fn find<C: Connection>(
+        db: &Surreal<C>,
+        name: &str,
+        source: Option<&HostSource>,
+    ) -> surrealdb::Result<Option<WildcardRecord>> {
+        db.query(
+            "SELECT * FROM wildcard WHERE host.name = $name \
+            AND source.path = $path AND source.start = $start LIMIT 1",
+        )
+        .bind(("name", name.to_string()))
+        .bind(("path", source.map(|s| s.path.clone())))
+        .bind(("start", source.map(|s| s.start)))
+        .await?
+        .take(0)
+    }
+
+    pub async fn get_all<C: Connection>(db: &Surreal<C>) -> surrealdb::Result<Vec<WildcardRecord>> {
+        db.select("wildcard").await
+    }
+}
+
 impl From<ssh2_config::Host> for Host {
     fn from(host: ssh2_config::Host) -> Self {
         let params: HostParams = host.params;
-        let pattern = host.pattern.index(0).pattern.clone();
+        let patterns: Vec<HostPattern> = host
+            .pattern
+            .iter()
+            .map(|clause| HostPattern {
+                pattern: clause.pattern.clone(),
+                negated: clause.negated,
+            })
+            .collect();
+        let aliases: Vec<String> = patterns
+            .iter()
+            .filter(|p| p.is_alias())
+            .map(|p| p.pattern.clone())
+            .collect();
+        let name = match aliases.first() {
+            Some(alias) => alias.clone(),
+            None => patterns
+                .iter()
+                .map(HostPattern::to_string)
+                .collect::<Vec<String>>()
+                .join(" "),
+        };
 
         Self {
-            name: pattern,
+            name,
+            patterns,
+            aliases,
             bind_address: params.bind_address,
             bind_interface: params.bind_interface,
             ca_signature_algorithms: params.ca_signature_algorithms,
@@ -318,4 +595,35 @@ impl From<ssh2_config::Host> for Host {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use crate::Hosts;
+    use db::memory_db;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn test_identical_wildcards_are_kept_apart() {
+        let dir = TempDir::new("wildcard").unwrap();
+        let db = memory_db().await.unwrap();
+
+        let config = dir.path().join("config");
+        fs::write(
+            &config,
+            "Host *\n    User a\n\nHost web\n    HostName 10.0.0.1\n\nHost *\n    User b\n",
+        )
+        .unwrap();
+        Hosts::parse_config(&db, config.clone(), None, None)
+            .await
+            .unwrap();
+
+        let mut starts: Vec<usize> = WildcardHost::get_all(&db)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|w| w.source.map(|s| s.start))
+            .collect();
+        starts.sort();
+        assert_eq!(starts, [1, 7]);
+    }
+}
@@ -0,0 +1,380 @@
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Custom attributes from `#--{key=value, ...}` comment, sorted by key.
+pub type Attributes = BTreeMap<String, AttributeValue>;
+
+/// Calendar date written as `YYYY-MM-DD`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl FromStr for Date {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid date `{}`, expected YYYY-MM-DD", s);
+
+        let mut parts = s.splitn(3, '-');
+        let (Some(year), Some(month), Some(day)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+            return Err(invalid());
+        }
+
+        let date = Date {
+            year: year.parse().map_err(|_| invalid())?,
+            month: month.parse().map_err(|_| invalid())?,
+            day: day.parse().map_err(|_| invalid())?,
+        };
+        if !(1..=12).contains(&date.month) || !(1..=date.days_in_month()).contains(&date.day) {
+            return Err(invalid());
+        }
+
+        Ok(date)
+    }
+}
+
+impl Date {
+    fn days_in_month(&self) -> u8 {
+        match self.month {
+            2 if self.is_leap_year() => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
+    fn is_leap_year(&self) -> bool {
+        (self.year.is_multiple_of(4) && !self.year.is_multiple_of(100))
+            || self.year.is_multiple_of(400)
+    }
+}
+
+impl Display for Date {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// Value of custom attribute, its type is inferred from how it's written.
+///
+/// Values are stored as plain booleans, numbers and strings, so they can be
+/// compared in queries. Dates are stored as `YYYY-MM-DD` strings which sort
+/// chronologically.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeValue {
+    Bool(bool),
+    Integer(i64),
+    Date(Date),
+    Text(String),
+}
+
+impl AttributeValue {
+    /// Infers type of `value`, `true`/`false`, integers and dates are recognized,
+    /// anything else is text. Double quotes force text.
+    pub fn parse(value: &str) -> Self {
+        if let Some(text) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+            return Self::Text(text.to_string());
+        }
+
+        if let Ok(value) = value.parse::<bool>() {
+            Self::Bool(value)
+        } else if let Ok(value) = value.parse::<i64>() {
+            Self::Integer(value)
+        } else if let Ok(value) = value.parse::<Date>() {
+            Self::Date(value)
+        } else {
+            Self::Text(value.to_string())
+        }
+    }
+}
+
+impl Display for AttributeValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bool(value) => write!(f, "{}", value),
+            Self::Integer(value) => write!(f, "{}", value),
+            Self::Date(value) => write!(f, "{}", value),
+            // Keep text which would otherwise be read back as another type or split
+            Self::Text(value)
+                if value.contains(',') || !matches!(Self::parse(value), Self::Text(_)) =>
+            {
+                write!(f, "\"{}\"", value)
+            }
+            Self::Text(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl Serialize for AttributeValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Bool(value) => serializer.serialize_bool(*value),
+            Self::Integer(value) => serializer.serialize_i64(*value),
+            Self::Date(value) => serializer.serialize_str(&value.to_string()),
+            Self::Text(value) => serializer.serialize_str(value),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for AttributeValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Stored {
+            Bool(bool),
+            Integer(i64),
+            Text(String),
+        }
+
+        Ok(match Stored::deserialize(deserializer)? {
+            Stored::Bool(value) => Self::Bool(value),
+            Stored::Integer(value) => Self::Integer(value),
+            Stored::Text(value) => match value.parse::<Date>() {
+                Ok(date) => Self::Date(date),
+                Err(_) => Self::Text(value),
+            },
+        })
+    }
+}
+
+/// Attribute key can be used in queries without escaping.
+pub fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Parses content of `#--{...}` comment, e.g. `owner=ops, note="a, b"`.
+pub fn parse_attributes(list: &str) -> Result<Attributes, String> {
+    let mut attributes = Attributes::new();
+
+    for pair in split_pairs(list)?
+        .into_iter()
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+    {
+        let Some((key, value)) = pair.split_once('=') else {
+            return Err(format!(
+                "attribute `{}` has no value, expected key=value",
+                pair
+            ));
+        };
+
+        let key = key.trim();
+        if !is_valid_key(key) {
+            return Err(format!(
+                "invalid attribute key `{}`, only letters, digits, `_` and `-` are allowed",
+                key
+            ));
+        }
+
+        attributes.insert(key.to_string(), AttributeValue::parse(value.trim()));
+    }
+
+    Ok(attributes)
+}
+
+/// Splits attribute list on commas which aren't inside double quotes.
+fn split_pairs(list: &str) -> Result<Vec<&str>, String> {
+    let mut pairs = vec![];
+    let mut start = 0;
+    let mut quoted = false;
+
+    for (index, c) in list.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                pairs.push(&list[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    if quoted {
+        return Err(format!("unterminated quote in `{}`", list.trim()));
+    }
+    pairs.push(&list[start..]);
+
+    Ok(pairs)
+}
+
+/// Renders attributes the way [`parse_attributes`] reads them.
+pub fn format_attributes(attributes: &Attributes) -> String {
+    attributes
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Comparison used by [`AttributeFilter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    /// SurrealQL operator.
+    pub fn operator(&self) -> &'static str {
+        match self {
+            Comparison::Equal => "=",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        }
+    }
+}
+
+/// Condition on a custom attribute, e.g. `env=prod` or `expires<2026-01-01`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttributeFilter {
+    pub key: String,
+    pub comparison: Comparison,
+    pub value: AttributeValue,
+}
+
+impl FromStr for AttributeFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Longer operator wins at the same position, so `<=` isn't read as `<`
+        const OPERATORS: [(&str, Comparison); 6] = [
+            ("!=", Comparison::NotEqual),
+            ("<=", Comparison::LessOrEqual),
+            (">=", Comparison::GreaterOrEqual),
+            ("=", Comparison::Equal),
+            ("<", Comparison::Less),
+            (">", Comparison::Greater),
+        ];
+
+        let (index, operator, comparison) = OPERATORS
+            .iter()
+            .filter_map(|(op, comparison)| s.find(op).map(|index| (index, *op, *comparison)))
+            .min_by_key(|(index, op, _)| (*index, usize::MAX - op.len()))
+            .ok_or_else(|| format!("`{}` is not an attribute filter, e.g. env=prod", s))?;
+
+        let key = s[..index].trim();
+        if !is_valid_key(key) {
+            return Err(format!("invalid attribute key `{}`", key));
+        }
+
+        Ok(Self {
+            key: key.to_string(),
+            comparison,
+            value: AttributeValue::parse(s[index + operator.len()..].trim()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_attributes() {
+        let attributes =
+            parse_attributes("owner=ops, env=prod, rotation=3, expires=2026-12-31, paged=true")
+                .unwrap();
+
+        assert_eq!(
+            attributes["owner"],
+            AttributeValue::Text(String::from("ops"))
+        );
+        assert_eq!(attributes["rotation"], AttributeValue::Integer(3));
+        assert_eq!(attributes["paged"], AttributeValue::Bool(true));
+        assert_eq!(
+            attributes["expires"],
+            AttributeValue::Date(Date {
+                year: 2026,
+                month: 12,
+                day: 31
+            })
+        );
+        assert_eq!(
+            format_attributes(&attributes),
+            "env=prod, expires=2026-12-31, owner=ops, paged=true, rotation=3"
+        );
+
+        assert!(parse_attributes("owner").is_err());
+        assert!(parse_attributes("on call=x").is_err());
+    }
+
+    #[test]
+    fn test_quoted_text_round_trips() {
+        let attributes = parse_attributes("ticket=INFRA-123, build=\"42\"").unwrap();
+
+        assert_eq!(
+            attributes["ticket"],
+            AttributeValue::Text(String::from("INFRA-123"))
+        );
+        assert_eq!(
+            attributes["build"],
+            AttributeValue::Text(String::from("42"))
+        );
+        assert_eq!(
+            format_attributes(&attributes),
+            "build=\"42\", ticket=INFRA-123"
+        );
+        assert!("2026-13-01".parse::<Date>().is_err());
+    }
+
+    #[test]
+    fn test_quoted_text_may_contain_commas() {
+        let attributes = parse_attributes("note=\"a, b\", env=prod").unwrap();
+
+        assert_eq!(
+            attributes["note"],
+            AttributeValue::Text(String::from("a, b"))
+        );
+        assert_eq!(
+            attributes["env"],
+            AttributeValue::Text(String::from("prod"))
+        );
+        assert_eq!(
+            parse_attributes(&format_attributes(&attributes)).unwrap(),
+            attributes
+        );
+        assert!(parse_attributes("note=\"a, b").is_err());
+    }
+
+    #[test]
+    fn test_date_checks_days_of_month() {
+        assert!("2026-02-31".parse::<Date>().is_err());
+        assert!("2026-02-29".parse::<Date>().is_err());
+        assert!("2026-04-31".parse::<Date>().is_err());
+        assert!("2028-02-29".parse::<Date>().is_ok());
+        assert!("2000-02-29".parse::<Date>().is_ok());
+        assert!("2100-02-29".parse::<Date>().is_err());
+        assert!("2026-12-31".parse::<Date>().is_ok());
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        let filter: AttributeFilter = "expires <= 2026-01-01".parse().unwrap();
+        assert_eq!(filter.key, "expires");
+        assert_eq!(filter.comparison, Comparison::LessOrEqual);
+        assert!(matches!(filter.value, AttributeValue::Date(_)));
+
+        let filter: AttributeFilter = "env!=prod".parse().unwrap();
+        assert_eq!(filter.comparison, Comparison::NotEqual);
+        assert_eq!(filter.value, AttributeValue::Text(String::from("prod")));
+
+        assert!("prod".parse::<AttributeFilter>().is_err());
+    }
+}
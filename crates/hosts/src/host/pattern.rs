@@ -0,0 +1,132 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+/// Single pattern from `Host` line, e.g. `web1`, `*.prod` or `!bastion`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HostPattern {
+    /// Pattern without leading `!`.
+    pub pattern: String,
+    pub negated: bool,
+}
+
+impl HostPattern {
+    pub fn new(pattern: &str) -> Self {
+        match pattern.strip_prefix('!') {
+            Some(p) => Self {
+                pattern: p.to_string(),
+                negated: true,
+            },
+            None => Self {
+                pattern: pattern.to_string(),
+                negated: false,
+            },
+        }
+    }
+
+    /// Pattern contains `*` or `?` and therefore doesn't name a single host.
+    pub fn is_wildcard(&self) -> bool {
+        self.pattern.contains(['*', '?'])
+    }
+
+    /// Pattern is a concrete name which can be used with `ssh <alias>`.
+    pub fn is_alias(&self) -> bool {
+        !self.negated && !self.is_wildcard()
+    }
+
+    /// Checks `name` against pattern, ignoring negation.
+    pub fn matches(&self, name: &str) -> bool {
+        match_pattern(&self.pattern.to_lowercase(), &name.to_lowercase())
+    }
+}
+
+impl Display for HostPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.negated {
+            write!(f, "!{}", self.pattern)
+        } else {
+            write!(f, "{}", self.pattern)
+        }
+    }
+}
+
+/// Evaluates pattern list the same way OpenSSH does.
+///
+/// `name` matches when at least one positive pattern matches and no negated one does.
+pub fn matches_list(patterns: &[HostPattern], name: &str) -> bool {
+    let mut matched = false;
+
+    for pattern in patterns {
+        if pattern.matches(name) {
+            if pattern.negated {
+                return false;
+            }
+            matched = true;
+        }
+    }
+
+    matched
+}
+
+/// Shell-like matching supporting only `*` and `?`, as used by ssh_config.
+pub fn match_pattern(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            n = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_pattern() {
+        assert!(match_pattern("*", "anything"));
+        assert!(match_pattern("*.prod", "db.prod"));
+        assert!(match_pattern("web?", "web1"));
+        assert!(!match_pattern("web?", "web10"));
+        assert!(match_pattern("*a*b", "xxaybb"));
+        assert!(!match_pattern("*.prod", "db.dev"));
+    }
+
+    #[test]
+    fn test_matches_list_with_negation() {
+        let patterns: Vec<HostPattern> = ["*.prod", "!bastion.prod"]
+            .iter()
+            .map(|p| HostPattern::new(p))
+            .collect();
+
+        assert!(matches_list(&patterns, "db.prod"));
+        assert!(!matches_list(&patterns, "bastion.prod"));
+        assert!(!matches_list(&patterns, "db.dev"));
+        assert!(!matches_list(&[HostPattern::new("!web")], "db"));
+    }
+
+    #[test]
+    fn test_pattern_kinds() {
+        assert!(HostPattern::new("web1").is_alias());
+        assert!(!HostPattern::new("!web1").is_alias());
+        assert!(HostPattern::new("*.prod").is_wildcard());
+        assert_eq!(HostPattern::new("!bastion").to_string(), "!bastion");
+    }
+}
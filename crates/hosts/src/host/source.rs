@@ -0,0 +1,123 @@
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use config::Source;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{cst::ConfigDocument, tokenizer::Stanza};
+
+use super::Host;
+
+/// Where a host is defined.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HostSource {
+    pub path: PathBuf,
+    /// First line of stanza including metadata comments, 1-based.
+    pub start: usize,
+    /// Last line of stanza, 1-based.
+    pub end: usize,
+    /// Hex encoded SHA-256 of stanza lines, changes whenever stanza is edited.
+    pub hash: String,
+    /// Name of configured source the file belongs to.
+    #[serde(default)]
+    pub origin: Option<String>,
+    /// Source is read-only, host is never written back.
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+impl HostSource {
+    /// Source of `stanza` read from file at `path` with `content`.
+    pub fn new(path: &Path, stanza: &Stanza, content: &str) -> Self {
+        let lines: Vec<&str> = content
+            .lines()
+            .skip(stanza.start - 1)
+            .take(stanza.end + 1 - stanza.start)
+            .collect();
+
+        Self {
+            path: canonical_path(path),
+            start: stanza.start,
+            end: stanza.end,
+            hash: content_hash(&lines.join("\n")),
+            origin: None,
+            read_only: false,
+        }
+    }
+
+    /// Records configured source the file belongs to.
+    pub fn with_origin(mut self, origin: Option<&Source>) -> Self {
+        self.origin = origin.map(|o| o.name().to_string());
+        self.read_only = origin.is_some_and(|o| o.read_only);
+        self
+    }
+
+    /// File still contains definition of `host`.
+    pub fn defines(&self, host: &Host) -> bool {
+        fs::read_to_string(&self.path)
+            .map(|content| ConfigDocument::parse(&content).span_of(host).is_some())
+            .unwrap_or(false)
+    }
+}
+
+impl Display for HostSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.path.display(), self.start)
+    }
+}
+
+/// Same file may be reached through different paths, e.g. by `Include`,
+/// sources always store canonical one.
+pub fn canonical_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Hex encoded SHA-256 of `content`.
+pub fn content_hash(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+/// Host defined more than once, only the first definition is stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Duplicate {
+    pub name: String,
+    /// Definition stored in database.
+    pub kept: HostSource,
+    /// Definition which was skipped.
+    pub ignored: HostSource,
+}
+
+impl Display for Duplicate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: host `{}` is already defined at {}, skipped",
+            self.ignored, self.name, self.kept
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::tokenize;
+
+    #[test]
+    fn test_source_of_stanza() {
+        let content = "Host a\n    User x\n\n# b\nHost b\n    User y\n";
+        let stanzas = tokenize(content);
+        let source = HostSource::new(Path::new("config"), &stanzas[1], content);
+
+        assert_eq!((source.start, source.end), (4, 6));
+        assert_eq!(source.hash, content_hash("# b\nHost b\n    User y"));
+        assert_ne!(
+            source.hash,
+            HostSource::new(Path::new("config"), &stanzas[0], content).hash
+        );
+        assert_eq!(source.to_string(), "config:4");
+    }
+}
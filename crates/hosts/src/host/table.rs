@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use surrealdb::{sql::Thing, Connection, Error, Response, Surreal};
+use surrealdb::{method::Query, sql::Thing, Connection, Error, Response, Surreal};
 
 pub trait TableName: 'static {
     const TABLE_NAME: &'static str;
@@ -39,6 +39,12 @@ impl<T: TableName> Record<T> {
         }
     }
 
+    /// Names are compared case-insensitively, e.g. `Infra/DB` and `infra/db` are
+    /// the same group. Stored name keeps case it was first written with.
+    pub fn key(name: &str) -> String {
+        name.to_lowercase()
+    }
+
     pub async fn get_id_by_name<C: Connection>(
         db: &Surreal<C>,
         name: String,
@@ -50,12 +56,11 @@ impl<T: TableName> Record<T> {
 
         let record: Option<Record> = db
             .query(format!(
-                "SELECT id FROM {} WHERE name = $name LIMIT 1",
+                "SELECT id FROM {} WHERE string::lowercase(name) = $name LIMIT 1",
                 T::TABLE_NAME
             ))
-            .bind(("name", name.clone().to_lowercase()))
-            .await
-            .unwrap()
+            .bind(("name", Self::key(&name)))
+            .await?
             .take(0)?;
 
         match record {
@@ -68,13 +73,9 @@ impl<T: TableName> Record<T> {
     where
         T: 'static,
     {
-        let record_id = match Self::get_id_by_name(db, data.name.clone()).await {
-            Ok(t) => match t {
-                Some(r) => r,
-                None => todo!(),
-            },
-            Err(e) => return Err(e),
-        };
+        let record_id = Self::get_id_by_name(db, data.name.clone())
+            .await?
+            .ok_or_else(|| invalid(format!("{} `{}` doesn't exist", T::TABLE_NAME, data.name)))?;
         let _: Option<Record<T>> = db
             .update((T::TABLE_NAME, &record_id.id.to_string()))
             .content(data)
@@ -94,37 +95,19 @@ impl<T: TableName> Record<T> {
         let _ = created.ok_or(Error::Db(surrealdb::error::Db::InvalidModel {
             message: String::from("Failed to create new tag"),
         }))?;
-        let record_id = match Self::get_id_by_name(db, name).await {
-            Ok(t) => match t {
-                Some(r) => r,
-                None => todo!(),
-            },
-            Err(e) => return Err(e),
-        };
-        Ok(record_id)
+        Self::get_id_by_name(db, name.clone())
+            .await?
+            .ok_or_else(|| invalid(format!("Failed to find new {} `{}`", T::TABLE_NAME, name)))
     }
 
+    /// Id of record `name`, created when there is none, see [`Self::key`].
     pub async fn create_or_update<C: Connection>(
         name: String,
         db: &Surreal<C>,
     ) -> surrealdb::Result<Thing> {
-        let existing: Option<Record<T>> = db
-            .query(format!(
-                "SELECT * FROM {} WHERE name = $name LIMIT 1",
-                T::TABLE_NAME,
-            ))
-            .bind(("name", name.clone()))
-            .await?
-            .take(0)?;
-
-        match existing {
-            // If tag exists, update it
-            Some(tag) => Self::update(db, tag).await,
-            // If tag doesn't exist, create new one
-            None => {
-                let tag = Self::create(db, name).await?;
-                Ok(tag)
-            }
+        match Self::get_id_by_name(db, name.clone()).await? {
+            Some(id) => Ok(id),
+            None => Self::create(db, name).await,
         }
     }
 
@@ -215,3 +198,383 @@ impl<T: TableName> Record<T> {
 
 pub type Tag = Record<TagTable>;
 pub type Group = Record<GroupTable>;
+
+/// Groups form a tree, group name is its whole path, e.g. `teams/payments/prod`,
+/// and every group is linked to its parent by `subgroup` relation.
+impl Record<GroupTable> {
+    /// Last segment of group path, e.g. `prod` for `teams/payments/prod`.
+    pub fn leaf(&self) -> &str {
+        self.name.rsplit('/').next().unwrap_or(&self.name)
+    }
+
+    /// Path of parent group, `None` for top level groups.
+    pub fn parent(&self) -> Option<&str> {
+        self.name.rsplit_once('/').map(|(parent, _)| parent)
+    }
+
+    /// Creates group `path` together with missing ancestors, each linked to its parent.
+    /// Returns id of the deepest group.
+    pub async fn create_path<C: Connection>(db: &Surreal<C>, path: &str) -> Result<Thing, Error> {
+        let mut current = String::new();
+        let mut parent: Option<Thing> = None;
+
+        for segment in path.split('/').map(str::trim).filter(|s| !s.is_empty()) {
+            if !current.is_empty() {
+                current.push('/');
+            }
+            current.push_str(segment);
+            // Existing groups keep their case, subgroups are created below them as stored
+            if let Some(stored) = Self::stored_name(db, &current).await? {
+                current = stored;
+            }
+
+            let group = Self::create_or_update(current.clone(), db).await?;
+            if let Some(parent) = &parent {
+                Self::add_subgroup(db, parent, &group).await?;
+            }
+            parent = Some(group);
+        }
+
+        parent.ok_or(Error::Db(surrealdb::error::Db::InvalidModel {
+            message: String::from("Empty group path"),
+        }))
+    }
+
+    async fn add_subgroup<C: Connection>(
+        db: &Surreal<C>,
+        parent: &Thing,
+        child: &Thing,
+    ) -> Result<(), Error> {
+        let existing: Option<Thing> = db
+            .query("SELECT VALUE id FROM subgroup WHERE in = $parent AND out = $child LIMIT 1")
+            .bind(("parent", parent.clone()))
+            .bind(("child", child.clone()))
+            .await?
+            .take(0)?;
+
+        if existing.is_none() {
+            db.query("RELATE $parent->subgroup->$child")
+                .bind(("parent", parent.clone()))
+                .bind(("child", child.clone()))
+                .await?
+                .check()?;
+        }
+
+        Ok(())
+    }
+
+    /// Renames group `path` to `name` under the same parent, e.g. `infra/db` to `infra/database`.
+    pub async fn rename<C: Connection>(
+        db: &Surreal<C>,
+        path: &str,
+        name: &str,
+    ) -> Result<(), Error> {
+        if name.is_empty() || name.contains('/') {
+            return Err(invalid(format!(
+                "invalid group name `{}`, it must not be empty or contain `/`",
+                name
+            )));
+        }
+
+        let path = Self::stored_name(db, path)
+            .await?
+            .ok_or_else(|| invalid(format!("group `{}` doesn't exist", path)))?;
+        let new_path = match path.rsplit_once('/') {
+            Some((parent, _)) => format!("{}/{}", parent, name),
+            None => name.to_string(),
+        };
+
+        Self::rename_subtree(db, &path, &new_path).await
+    }
+
+    /// Moves group `path` with its subgroups below `parent`, to top level when `None`.
+    /// Missing parent is created.
+    pub async fn move_to<C: Connection>(
+        db: &Surreal<C>,
+        path: &str,
+        parent: Option<&str>,
+    ) -> Result<(), Error> {
+        let (Some(id), Some(path)) = (
+            Self::get_id_by_name(db, path.to_string()).await?,
+            Self::stored_name(db, path).await?,
+        ) else {
+            return Err(invalid(format!("group `{}` doesn't exist", path)));
+        };
+        let path = path.as_str();
+        let leaf = path.rsplit('/').next().unwrap_or(path);
+        // Existing parent keeps its case, so paths of its subgroups stay consistent
+        let parent = match parent {
+            Some(parent) => Some(
+                Self::stored_name(db, parent)
+                    .await?
+                    .unwrap_or_else(|| parent.to_string()),
+            ),
+            None => None,
+        };
+        let parent = parent.as_deref();
+
+        let new_path = match parent {
+            Some(parent)
+                if Self::key(parent) == Self::key(path)
+                    || Self::key(parent).starts_with(&format!("{}/", Self::key(path))) =>
+            {
+                return Err(invalid(format!(
+                    "group `{}` can't be moved below itself",
+                    path
+                )));
+            }
+            Some(parent) => format!("{}/{}", parent, leaf),
+            None => leaf.to_string(),
+        };
+
+        let renames = Self::subtree_renames(db, path, &new_path).await?;
+        let parent = match parent {
+            Some(parent) => Some(Self::create_path(db, parent).await?),
+            None => None,
+        };
+
+        // Subtree is renamed and linked to its new parent together, or not at all
+        let mut query = with_renames(db.query("BEGIN TRANSACTION;"), renames)
+            .query("DELETE subgroup WHERE out = $group;")
+            .bind(("group", id));
+        if let Some(parent) = parent {
+            query = query
+                .query("RELATE $parent->subgroup->$group;")
+                .bind(("parent", parent));
+        }
+        query.query("COMMIT TRANSACTION;").await?.check()?;
+
+        Ok(())
+    }
+
+    /// Name of group `path` as it is stored, see [`Self::key`].
+    async fn stored_name<C: Connection>(
+        db: &Surreal<C>,
+        path: &str,
+    ) -> Result<Option<String>, Error> {
+        let names: Vec<String> = db
+            .query("SELECT VALUE name FROM group WHERE string::lowercase(name) = $key LIMIT 1")
+            .bind(("key", Self::key(path)))
+            .await?
+            .take(0)?;
+
+        Ok(names.into_iter().next())
+    }
+
+    /// Deletes group `path` with all its subgroups. Hosts are kept, only their
+    /// membership in deleted groups is removed.
+    pub async fn delete_subtree<C: Connection>(db: &Surreal<C>, path: &str) -> Result<(), Error> {
+        let names = Self::with_descendants(db, vec![path.to_string()]).await?;
+
+        db.query(
+            "LET $groups = (SELECT VALUE id FROM group WHERE name IN $names);
+            DELETE groupped WHERE in IN $groups;
+            DELETE subgroup WHERE in IN $groups OR out IN $groups;
+            DELETE group WHERE id IN $groups;",
+        )
+        .bind(("names", names))
+        .await?
+        .check()?;
+
+        Ok(())
+    }
+
+    /// Changes path prefix `old` to `new` on group `old` and every group below it.
+    ///
+    /// Relations refer to groups by id, so hosts and subgroups stay attached. Every
+    /// group is renamed in one transaction, so failure leaves the tree as it was.
+    async fn rename_subtree<C: Connection>(
+        db: &Surreal<C>,
+        old: &str,
+        new: &str,
+    ) -> Result<(), Error> {
+        let renames = Self::subtree_renames(db, old, new).await?;
+        if renames.is_empty() {
+            return Ok(());
+        }
+
+        with_renames(db.query("BEGIN TRANSACTION;"), renames)
+            .query("COMMIT TRANSACTION;")
+            .await?
+            .check()?;
+
+        Ok(())
+    }
+
+    /// Current and new names of group `old` and groups below it when it becomes `new`,
+    /// none when the name stays the same.
+    async fn subtree_renames<C: Connection>(
+        db: &Surreal<C>,
+        old: &str,
+        new: &str,
+    ) -> Result<Vec<(String, String)>, Error> {
+        let Some(old) = Self::stored_name(db, old).await? else {
+            return Err(invalid(format!("group `{}` doesn't exist", old)));
+        };
+        if old == new {
+            return Ok(vec![]);
+        }
+
+        let renames: Vec<(String, String)> = Self::with_descendants(db, vec![old.clone()])
+            .await?
+            .into_iter()
+            .map(|name| {
+                let renamed = format!("{}{}", new, &name[old.len()..]);
+                (name, renamed)
+            })
+            .collect();
+
+        for (name, renamed) in &renames {
+            // Changing only case of a name keeps the same group
+            if Self::key(name) != Self::key(renamed)
+                && Self::get_id_by_name(db, renamed.clone()).await?.is_some()
+            {
+                return Err(invalid(format!("group `{}` already exists", renamed)));
+            }
+        }
+
+        Ok(renames)
+    }
+
+    /// Names of groups `names`, as they are stored, together with every group below them.
+    pub async fn with_descendants<C: Connection>(
+        db: &Surreal<C>,
+        names: Vec<String>,
+    ) -> Result<Vec<String>, Error> {
+        let mut level = vec![];
+        for name in names {
+            level.push(Self::stored_name(db, &name).await?.unwrap_or(name));
+        }
+        let mut all = level.clone();
+
+        while !level.is_empty() {
+            let children: Vec<String> = db
+                .query("SELECT VALUE out.name FROM subgroup WHERE in.name IN $names")
+                .bind(("names", level))
+                .await?
+                .take(0)?;
+
+            level = children.into_iter().filter(|c| !all.contains(c)).collect();
+            all.extend(level.iter().cloned());
+        }
+
+        Ok(all)
+    }
+}
+
+/// Adds statements renaming groups to `query`, to be run in a transaction.
+fn with_renames<C: Connection>(
+    mut query: Query<'_, C>,
+    renames: Vec<(String, String)>,
+) -> Query<'_, C> {
+    for (index, (name, renamed)) in renames.into_iter().enumerate() {
+        query = query
+            .query(format!(
+                "UPDATE group SET name = $new{0} WHERE name = $old{0};",
+                index
+            ))
+            .bind((format!("old{}", index), name))
+            .bind((format!("new{}", index), renamed));
+    }
+    query
+}
+
+fn invalid(message: String) -> Error {
+    Error::Db(surrealdb::error::Db::InvalidModel { message })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::{EnhancedHost, Host};
+    use db::memory_db;
+
+    #[tokio::test]
+    async fn test_move_rename_and_delete_subtree() -> Result<(), Error> {
+        let db = memory_db().await.unwrap();
+
+        let primary = Group::create_path(&db, "infra/db/primary").await?;
+        let host = EnhancedHost::create(
+            &db,
+            EnhancedHost {
+                host: Host {
+                    name: String::from("pg1"),
+                    aliases: vec![String::from("pg1")],
+                    ..Default::default()
+                },
+                comment: None,
+                source: None,
+                attributes: Default::default(),
+                option_comments: Default::default(),
+            },
+        )
+        .await?;
+        EnhancedHost::add_group(&db, &host.id, &primary).await?;
+
+        assert!(Group::rename(&db, "infra/db", "").await.is_err());
+        assert!(Group::rename(&db, "infra/db", "a/b").await.is_err());
+        Group::rename(&db, "infra/db", "database").await?;
+        let groups = EnhancedHost::get_groups(&db, &host.id).await?;
+        assert_eq!(groups[&primary].name, "infra/database/primary");
+
+        Group::move_to(&db, "infra/database", Some("platform")).await?;
+        let groups = EnhancedHost::get_groups(&db, &host.id).await?;
+        assert_eq!(groups[&primary].name, "platform/database/primary");
+        assert_eq!(groups[&primary].parent(), Some("platform/database"));
+        assert_eq!(
+            Group::with_descendants(&db, vec![String::from("infra")]).await?,
+            ["infra"]
+        );
+        assert!(Group::move_to(&db, "platform", Some("platform/database"))
+            .await
+            .is_err());
+
+        // Names are matched regardless of case, stored case is kept
+        let mixed = Group::create_path(&db, "Platform/Database/Primary").await?;
+        assert_eq!(mixed, primary);
+        Group::rename(&db, "PLATFORM/database", "DB").await?;
+        let groups = EnhancedHost::get_groups(&db, &host.id).await?;
+        assert_eq!(groups[&primary].name, "platform/DB/primary");
+        let replica = Group::create_path(&db, "PLATFORM/db/replica").await?;
+        assert_eq!(
+            Group::get_record(&db, &replica).await?.unwrap().name,
+            "platform/DB/replica"
+        );
+
+        Group::delete_subtree(&db, "platform").await?;
+        assert!(EnhancedHost::get_groups(&db, &host.id).await?.is_empty());
+        assert!(EnhancedHost::get_host_by_name(&db, String::from("pg1"))
+            .await?
+            .is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_move_leaves_tree_as_it_was() -> Result<(), Error> {
+        let db = memory_db().await.unwrap();
+
+        let primary = Group::create_path(&db, "infra/db/primary").await?;
+        db.query(
+            "CREATE group:blocked SET name = 'blocked';
+            DEFINE FIELD OVERWRITE in ON subgroup TYPE record<group> \
+            ASSERT $value != group:blocked PERMISSIONS FULL;",
+        )
+        .await?
+        .check()?;
+
+        assert!(Group::move_to(&db, "infra/db", Some("blocked"))
+            .await
+            .is_err());
+        assert_eq!(
+            Group::get_record(&db, &primary).await?.unwrap().name,
+            "infra/db/primary"
+        );
+        assert_eq!(
+            Group::with_descendants(&db, vec![String::from("infra")]).await?,
+            ["infra", "infra/db", "infra/db/primary"]
+        );
+
+        Ok(())
+    }
+}
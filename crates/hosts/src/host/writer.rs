@@ -0,0 +1,359 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+
+use crate::{rule::MatchRule, tokenizer::Directive};
+
+use super::{
+    attribute::{format_attributes, Attributes},
+    Host,
+};
+
+/// sshed metadata kept in comments of a stanza.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Metadata {
+    /// Description above `Host` line, may span several lines.
+    pub comment: Option<String>,
+    pub groups: Vec<String>,
+    pub tags: Vec<String>,
+    pub attributes: Attributes,
+    /// Comments written above options, keyed by lowercase keyword.
+    pub option_comments: BTreeMap<String, String>,
+}
+
+/// Renders host as ssh config stanza, preceded by sshed metadata comments.
+///
+/// Groups are written as `#--(a, b)`, tags as `#--[a, b]`, attributes as
+/// `#--{key=value}` and every line of comment as `# line`, the same conventions
+/// `Hosts::parse_config` reads. Option comments go above the first option with
+/// their keyword.
+pub fn write_host(host: &Host, metadata: &Metadata) -> String {
+    let mut out = String::new();
+
+    for line in metadata_lines(metadata) {
+        out.push_str(&format!("{}\n", line));
+    }
+
+    out.push_str(&format!("{}\n", header(host)));
+
+    let mut commented: Vec<String> = vec![];
+    for option in options(host) {
+        let keyword = option.keyword.to_lowercase();
+        if !commented.contains(&keyword) {
+            if let Some(comment) = metadata.option_comments.get(&keyword) {
+                for line in comment_lines(comment) {
+                    out.push_str(&format!("    {}\n", line));
+                }
+            }
+            commented.push(keyword);
+        }
+        out.push_str(&format!("    {}\n", option));
+    }
+
+    out
+}
+
+/// Renders rule as `Match` block, preceded by `comment` lines.
+pub fn write_rule(rule: &MatchRule, comment: Option<&str>) -> String {
+    let mut out = String::new();
+
+    for line in comment.map(comment_lines).unwrap_or_default() {
+        out.push_str(&format!("{}\n", line));
+    }
+
+    out.push_str(&format!("Match {}\n", rule.condition));
+    for option in &rule.options {
+        out.push_str(&format!(
+            "    {}\n",
+            directive(&option.keyword, option.args.clone())
+        ));
+    }
+
+    out
+}
+
+/// sshed metadata comment lines placed above `Host` line.
+pub(crate) fn metadata_lines(metadata: &Metadata) -> Vec<String> {
+    let mut lines = vec![];
+
+    if !metadata.groups.is_empty() {
+        lines.push(format!("#--({})", metadata.groups.join(", ")));
+    }
+    if !metadata.tags.is_empty() {
+        lines.push(format!("#--[{}]", metadata.tags.join(", ")));
+    }
+    if !metadata.attributes.is_empty() {
+        lines.push(format!(
+            "#--{{{}}}",
+            format_attributes(&metadata.attributes)
+        ));
+    }
+    if let Some(comment) = &metadata.comment {
+        lines.extend(comment_lines(comment));
+    }
+
+    lines
+}
+
+/// `comment` as `# line` lines, empty lines are written as bare `#`.
+pub(crate) fn comment_lines(comment: &str) -> Vec<String> {
+    comment
+        .lines()
+        .map(|line| {
+            if line.is_empty() {
+                String::from("#")
+            } else {
+                format!("# {}", line)
+            }
+        })
+        .collect()
+}
+
+/// `Host` line with every pattern of host.
+pub(crate) fn header(host: &Host) -> Directive {
+    let patterns: Vec<String> = if host.patterns.is_empty() {
+        vec![host.name.clone()]
+    } else {
+        host.patterns.iter().map(ToString::to_string).collect()
+    };
+
+    directive("Host", patterns)
+}
+
+/// Every option set on host, as directives in the order they are written.
+pub fn options(host: &Host) -> Vec<Directive> {
+    let mut options = vec![];
+
+    push(
+        &mut options,
+        "HostName",
+        host.host_name.as_ref().map(single),
+    );
+    push(&mut options, "User", host.user.as_ref().map(single));
+    push(&mut options, "Port", host.port.map(|p| single(&p)));
+    // Paths are written back as the user spelled them
+    for file in host.identity_files() {
+        push(&mut options, "IdentityFile", Some(vec![file.raw]));
+    }
+    push(
+        &mut options,
+        "ProxyJump",
+        host.proxy_jump.as_deref().map(list),
+    );
+    push(
+        &mut options,
+        "BindAddress",
+        host.bind_address.as_ref().map(single),
+    );
+    push(
+        &mut options,
+        "BindInterface",
+        host.bind_interface.as_ref().map(single),
+    );
+    push(
+        &mut options,
+        "CASignatureAlgorithms",
+        host.ca_signature_algorithms.as_deref().map(list),
+    );
+    push(
+        &mut options,
+        "CertificateFile",
+        host.certificate().map(|file| vec![file.raw]),
+    );
+    push(&mut options, "Ciphers", host.ciphers.as_deref().map(list));
+    push(&mut options, "Compression", host.compression.map(yes_no));
+    push(
+        &mut options,
+        "ConnectionAttempts",
+        host.connection_attempts.map(|a| single(&a)),
+    );
+    push(
+        &mut options,
+        "ConnectTimeout",
+        host.connect_timeout.map(seconds),
+    );
+    push(
+        &mut options,
+        "HostKeyAlgorithms",
+        host.host_key_algorithms.as_deref().map(list),
+    );
+    push(
+        &mut options,
+        "IgnoreUnknown",
+        host.ignore_unknown.as_deref().map(list),
+    );
+    push(
+        &mut options,
+        "KexAlgorithms",
+        host.kex_algorithms.as_deref().map(list),
+    );
+    push(&mut options, "MACs", host.mac.as_deref().map(list));
+    push(
+        &mut options,
+        "PubkeyAcceptedAlgorithms",
+        host.pubkey_accepted_algorithms.as_deref().map(list),
+    );
+    push(
+        &mut options,
+        "PubkeyAuthentication",
+        host.pubkey_authentication.map(yes_no),
+    );
+    push(
+        &mut options,
+        "RemoteForward",
+        host.remote_forward.map(|p| single(&p)),
+    );
+    push(
+        &mut options,
+        "ServerAliveInterval",
+        host.server_alive_interval.map(seconds),
+    );
+    push(
+        &mut options,
+        "TCPKeepAlive",
+        host.tcp_keep_alive.map(yes_no),
+    );
+    #[cfg(target_os = "macos")]
+    push(&mut options, "UseKeychain", host.use_keychain.map(yes_no));
+
+    options.extend(fields(&host.unsupported_fields));
+    options.extend(fields(&host.ignored_fields));
+
+    options
+}
+
+fn push(options: &mut Vec<Directive>, keyword: &str, args: Option<Vec<String>>) {
+    if let Some(args) = args {
+        options.push(directive(keyword, args));
+    }
+}
+
+fn directive(keyword: &str, args: Vec<String>) -> Directive {
+    Directive {
+        keyword: keyword.to_string(),
+        args,
+        line: 0,
+        comment: None,
+    }
+}
+
+/// Fields kept verbatim by the parser, sorted so output is stable.
+fn fields(fields: &HashMap<String, Vec<String>>) -> Vec<Directive> {
+    let mut keys: Vec<&String> = fields.keys().collect();
+    keys.sort();
+
+    keys.into_iter()
+        .map(|key| directive(key, fields[key].clone()))
+        .collect()
+}
+
+fn single<T: ToString>(value: &T) -> Vec<String> {
+    vec![value.to_string()]
+}
+
+fn list(values: &[String]) -> Vec<String> {
+    vec![values.join(",")]
+}
+
+fn yes_no(value: bool) -> Vec<String> {
+    if value {
+        vec![String::from("yes")]
+    } else {
+        vec![String::from("no")]
+    }
+}
+
+fn seconds(duration: Duration) -> Vec<String> {
+    vec![duration.as_secs().to_string()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        host::{attribute::AttributeValue, pattern::HostPattern},
+        tokenizer::tokenize,
+    };
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_write_host() {
+        let host = Host {
+            name: String::from("dev-server"),
+            patterns: vec![HostPattern::new("dev-server"), HostPattern::new("!dev-old")],
+            aliases: vec![String::from("dev-server")],
+            host_name: Some(String::from("192.168.1.100")),
+            user: Some(String::from("developer")),
+            port: Some(2222),
+            identity_file: Some(vec![
+                PathBuf::from("~/.ssh/id_rsa"),
+                PathBuf::from("~/my key"),
+            ]),
+            proxy_jump: Some(vec![
+                String::from("prod-server"),
+                String::from("github.com"),
+            ]),
+            connect_timeout: Some(Duration::from_secs(10)),
+            compression: Some(false),
+            ignored_fields: HashMap::from([(
+                String::from("ForwardAgent"),
+                vec![String::from("yes")],
+            )]),
+            ..Default::default()
+        };
+
+        let metadata = Metadata {
+            comment: Some(String::from("Development server\n\nReached through VPN")),
+            groups: vec![String::from("servers"), String::from("dev")],
+            tags: vec![String::from("abc")],
+            attributes: Attributes::from([(
+                String::from("owner"),
+                AttributeValue::Text(String::from("ops")),
+            )]),
+            option_comments: BTreeMap::from([(
+                String::from("identityfile"),
+                String::from("work key first"),
+            )]),
+        };
+        let config = write_host(&host, &metadata);
+
+        assert_eq!(
+            config,
+            "#--(servers, dev)\n\
+             #--[abc]\n\
+             #--{owner=ops}\n\
+             # Development server\n\
+             #\n\
+             # Reached through VPN\n\
+             Host dev-server !dev-old\n\
+             \x20   HostName 192.168.1.100\n\
+             \x20   User developer\n\
+             \x20   Port 2222\n\
+             \x20   # work key first\n\
+             \x20   IdentityFile ~/.ssh/id_rsa\n\
+             \x20   IdentityFile \"~/my key\"\n\
+             \x20   ProxyJump prod-server,github.com\n\
+             \x20   Compression no\n\
+             \x20   ConnectTimeout 10\n\
+             \x20   ForwardAgent yes\n"
+        );
+
+        let stanzas = tokenize(&config);
+        assert_eq!(stanzas.len(), 1);
+        assert_eq!(stanzas[0].metadata.len(), 6);
+        assert_eq!(stanzas[0].directives[4].args, ["~/my key"]);
+        assert_eq!(stanzas[0].option_comments(), metadata.option_comments);
+    }
+
+    #[test]
+    fn test_write_rule() {
+        let block = "Match host *.prod exec \"test -f /tmp/vpn\"\n    proxyjump bastion\n";
+        let rule = MatchRule::from_stanza(&tokenize(block)[0]).unwrap();
+
+        assert_eq!(
+            write_rule(&rule, Some("may apply")),
+            format!("# may apply\n{}", block)
+        );
+    }
+}
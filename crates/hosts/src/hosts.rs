@@ -1,41 +1,149 @@
 use std::{
-    fs::File,
-    io::{BufReader, Error, ErrorKind, Read, Result},
-    path::PathBuf,
+    collections::HashMap,
+    io::{Error, ErrorKind, Result},
+    path::{Path, PathBuf},
 };
 
+pub mod cst;
 pub mod host;
+pub mod include;
 pub mod querry;
+pub mod resolve;
+pub mod rule;
+pub mod sync;
+pub mod tokenizer;
+use config::Source;
+use diagnostics::Diagnostic;
 use host::{
+    attribute::{parse_attributes, Attributes},
+    source::{canonical_path, Duplicate, HostSource},
     table::{Group, Tag},
-    EnhancedHost,
+    writer, EnhancedHost, Host, WildcardHost,
 };
-use ssh2_config::{ParseRule, SshConfig};
+use include::{IncludeResolver, SourceFile};
+use rule::{Applicability, EnhancedRule, MatchRule};
+use ssh2_config::{ParseRule, SshConfig, SshParserError};
 use surrealdb::{sql::Thing, Connection, Surreal};
+use sync::{Change, SyncReport};
+use tokenizer::{Stanza, StanzaKind};
 
 #[derive(Debug)]
 pub struct Hosts {}
 
+/// Outcome of ingesting ssh config files.
+#[derive(Debug, Default)]
+pub struct IngestReport {
+    /// Canonical paths of every file read.
+    pub files: Vec<PathBuf>,
+    /// Hosts defined more than once, only the first definition is stored.
+    pub duplicates: Vec<Duplicate>,
+    /// Problems found in files, hosts and options they concern may be missing.
+    pub diagnostics: Vec<Diagnostic>,
+    /// Added, changed and, after [`Hosts::sync`], removed hosts per file.
+    pub changes: SyncReport,
+}
+
+impl IngestReport {
+    pub fn extend(&mut self, other: IngestReport) {
+        self.files.extend(other.files);
+        self.duplicates.extend(other.duplicates);
+        self.diagnostics.extend(other.diagnostics);
+        self.changes.extend(other.changes);
+    }
+}
+
 impl Hosts {
+    /// Parses ssh config at `path` together with every file pulled in by its `Include` directives.
+    ///
+    /// Hosts remember `origin`, its group and tags are added to ones from metadata comments.
     pub async fn parse_config<C: Connection>(
         db: &Surreal<C>,
         path: PathBuf,
         group: Option<String>,
-    ) -> Result<()> {
-        let mut reader = BufReader::new(File::open(path)?);
-        let mut content = String::new();
-        reader.read_to_string(&mut content)?;
-
-        let blocks: Vec<&str> = content
-            .split("\n\n")
-            .map(|block| block.trim())
-            .filter(|block| !block.is_empty())
-            .collect();
+        origin: Option<&Source>,
+    ) -> Result<IngestReport> {
+        let mut report = IngestReport::default();
+        let sources = IncludeResolver::new()
+            .with_origin(origin.cloned())
+            .collect(path, group, &mut report.diagnostics)?;
+        let mut seen = HashMap::new();
+        report
+            .files
+            .extend(sources.iter().map(|source| canonical_path(&source.path)));
+
+        // Hosts are defined in the order ssh reads them, so the first definition wins
+        for (index, stanzas) in include::read_order(&sources) {
+            exctract_host(stanzas, &sources[index], db, &mut seen, &mut report).await?;
+        }
+
+        Ok(report)
+    }
+
+    /// Parses single ssh config file, its `Include` directives aren't followed.
+    ///
+    /// Used to re-ingest a file which changed, files it includes are handled on their own.
+    pub async fn parse_file<C: Connection>(
+        db: &Surreal<C>,
+        source: &SourceFile,
+    ) -> Result<IngestReport> {
+        let mut report = IngestReport::default();
+        let stanzas = tokenizer::tokenize(&source.content);
+        report.files.push(canonical_path(&source.path));
+
+        exctract_host(stanzas, source, db, &mut HashMap::new(), &mut report).await?;
+
+        Ok(report)
+    }
+
+    /// Removes hosts which disappeared from files read during ingest, run it once
+    /// every file was parsed. Removed hosts are added to `report`.
+    pub async fn sync<C: Connection>(db: &Surreal<C>, report: &mut IngestReport) -> Result<()> {
+        sync::remove_stale(db, report).await
+    }
+
+    /// Stored host known as `name` as ssh config stanza, followed by `Match` blocks
+    /// which apply, or may apply, to it. `None` when no host is known as `name`.
+    pub async fn describe<C: Connection>(db: &Surreal<C>, name: &str) -> Result<Option<String>> {
+        let to_io = |e: surrealdb::Error| Error::new(ErrorKind::Other, e.to_string());
+
+        let Some(record) = EnhancedHost::get_host_by_alias(db, name.to_string())
+            .await
+            .map_err(to_io)?
+        else {
+            return Ok(None);
+        };
+        let mut out = EnhancedHost::render(db, &record).await.map_err(to_io)?;
+
+        // Stanza keeps paths as written, files ssh opens are listed below it
+        let files = record.host.identity_files().into_iter();
+        for file in files.chain(record.host.certificate()) {
+            if file.expanded.to_string_lossy() != file.raw {
+                out.push_str(&format!("# {} is {}\n", file.raw, file.expanded.display()));
+            }
+        }
+
+        for (rule, applicability) in EnhancedRule::applying_to(db, &record.host)
+            .await
+            .map_err(to_io)?
+        {
+            let mut comment = match applicability {
+                Applicability::Unknown => {
+                    String::from("may apply, depends on criteria ssh evaluates when connecting")
+                }
+                _ => String::from("applies"),
+            };
+            if let Some(source) = &rule.source {
+                comment.push_str(&format!(", {}", source));
+            }
+            if let Some(description) = &rule.comment {
+                comment = format!("{}\n{}", description, comment);
+            }
 
-        match exctract_host(blocks, db, group).await {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e),
+            out.push('\n');
+            out.push_str(&writer::write_rule(&rule.rule, Some(&comment)));
         }
+
+        Ok(Some(out))
     }
 
     pub async fn get_all_hosts<C: Connection>(db: &Surreal<C>) -> Result<Vec<EnhancedHost>> {
@@ -45,56 +153,229 @@ impl Hosts {
     }
 }
 
+/// Derives group path from location of ssh config file below `base`.
+///
+/// Every directory between `base` and the file becomes a parent group, so
+/// `config.d/teams/payments/prod` read from `config.d` is in group `teams/payments/prod`.
+/// Files outside `base` only get their file name.
+pub fn groupname_from_path(path: &Path, base: &Path) -> Option<String> {
+    let segments: Vec<&str> = match path.strip_prefix(base) {
+        Ok(relative) => relative
+            .components()
+            .filter_map(|c| c.as_os_str().to_str())
+            .collect(),
+        Err(_) => path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .into_iter()
+            .collect(),
+    };
+
+    (!segments.is_empty()).then(|| segments.join("/"))
+}
+
+/// Directory part of glob `pattern` which doesn't contain any wildcard.
+pub fn glob_base(pattern: &Path) -> PathBuf {
+    let mut base = PathBuf::new();
+    for component in pattern.components() {
+        if component
+            .as_os_str()
+            .to_str()
+            .is_some_and(|c| c.contains(['*', '?', '[']))
+        {
+            return base;
+        }
+        base.push(component);
+    }
+
+    // Pattern without wildcards names a single file
+    base.parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
 async fn exctract_host<C: Connection>(
-    blocks: Vec<&str>,
+    stanzas: Vec<Stanza>,
+    file: &SourceFile,
     db: &Surreal<C>,
-    group: Option<String>,
+    seen: &mut HashMap<String, HostSource>,
+    report: &mut IngestReport,
 ) -> Result<()> {
-    for block in blocks {
-        let mut lines: Vec<&str> = block.lines().collect();
-        let mut groups: Vec<Thing> = vec![];
-        let mut tags: Vec<Thing> = vec![];
-        let mut comment = None;
+    let group = file.full_group();
+
+    // Tags of the source are merged with ones from metadata comments
+    let mut origin_tags = vec![];
+    for name in file.origin.iter().flat_map(|o| o.tags.clone()) {
+        if let Ok(tag) = Tag::create_or_update(name, db).await {
+            origin_tags.push(tag);
+        }
+    }
+
+    for mut stanza in stanzas {
+        // Options before first `Host` line only carry `Include` directives and defaults
+        if stanza.kind == StanzaKind::Global {
+            continue;
+        }
+
+        let StanzaMetadata {
+            groups,
+            mut tags,
+            comment,
+            attributes,
+        } = extract_metadata(&stanza, file, db, &mut report.diagnostics).await;
+        for tag in &origin_tags {
+            if !tags.contains(tag) {
+                tags.push(tag.clone());
+            }
+        }
 
-        extract_metadata(&mut lines, &mut groups, &mut tags, &mut comment, db).await;
+        let source =
+            HostSource::new(&file.path, &stanza, &file.content).with_origin(file.origin.as_ref());
 
-        let host_config = lines.join("\n");
-        let mut host_reader = host_config.as_bytes();
+        // Match blocks are conditional rules, not hosts
+        if let Some(rule) = MatchRule::from_stanza(&stanza) {
+            let rule = EnhancedRule {
+                rule,
+                comment,
+                source: Some(source),
+            };
+            match EnhancedRule::create_or_update(db, rule).await {
+                Ok(id) => report.changes.keep(id),
+                Err(e) => return Err(Error::new(ErrorKind::Other, e.to_string())),
+            }
+            continue;
+        }
 
-        if let Ok(config) = SshConfig::default().parse(&mut host_reader, ParseRule::STRICT) {
-            if let Some(host) = config.get_hosts().get(1).cloned() {
-                let enh_host = EnhancedHost {
-                    host: host.into(),
+        // Included files are parsed on their own
+        stanza
+            .directives
+            .retain(|directive| !directive.is("include"));
+
+        if let Some(mut host) = parse_host(&stanza, file, &mut report.diagnostics) {
+            // Wildcard stanzas don't describe any host, store them separately without relations
+            if host.is_wildcard() {
+                let wildcard = WildcardHost {
+                    host,
                     comment,
+                    source: Some(source),
                 };
-
-                // Create Host
-                let host = match EnhancedHost::create_or_update(db, enh_host).await {
-                    Ok(t) => t,
+                match WildcardHost::create_or_update(db, wildcard).await {
+                    Ok(id) => report.changes.keep(id),
                     Err(e) => return Err(Error::new(ErrorKind::Other, e.to_string())),
-                };
-
-                // Add group from filename
-                if let Some(value) = add_grp_from_filename(db, &group, &groups, &host.id).await {
-                    return value;
                 }
+                continue;
+            }
 
-                // Add tags from config file
-                add_tags(db, &tags, &host.id).await;
+            // First definition of every alias wins, the same as in ssh
+            for (alias, kept) in earlier_definitions(db, &host, &source, seen).await? {
+                report.diagnostics.push(Diagnostic::warning(
+                    &file.path,
+                    stanza
+                        .header
+                        .as_ref()
+                        .map(|h| h.line)
+                        .unwrap_or(stanza.start),
+                    0,
+                    format!(
+                        "host `{}` is already defined at {}, it is ignored here",
+                        alias, kept
+                    ),
+                ));
+                host.aliases.retain(|a| !a.eq_ignore_ascii_case(&alias));
+                report.duplicates.push(Duplicate {
+                    name: alias,
+                    kept,
+                    ignored: source.clone(),
+                });
+            }
+            // Remaining aliases are still defined by this stanza
+            match host.aliases.first() {
+                Some(alias) => host.name = alias.clone(),
+                None => continue,
+            }
+            for alias in &host.aliases {
+                seen.insert(alias.to_lowercase(), source.clone());
+            }
+
+            let change = match EnhancedHost::get_host_by_name(db, host.name.clone()).await {
+                Ok(Some(record))
+                    if record
+                        .source
+                        .as_ref()
+                        .is_some_and(|s| s.hash == source.hash) =>
+                {
+                    Change::Unchanged
+                }
+                Ok(Some(_)) => Change::Changed,
+                Ok(None) => Change::Added,
+                Err(e) => return Err(Error::new(ErrorKind::Other, e.to_string())),
+            };
+            report
+                .changes
+                .record(source.path.clone(), host.name.clone(), change);
 
-                // Add groups from config file
-                add_grps(db, &groups, &host.id).await;
+            let enh_host = EnhancedHost {
+                host,
+                comment,
+                source: Some(source),
+                attributes,
+                option_comments: stanza.option_comments(),
+            };
 
-                // remove tags and groups that are missing in config file.
-                remove_unused_tags(db, tags, &host.id).await;
+            // Create Host
+            let host = match EnhancedHost::create_or_update(db, enh_host).await {
+                Ok(t) => t,
+                Err(e) => return Err(Error::new(ErrorKind::Other, e.to_string())),
+            };
 
-                remove_unused_grps(db, &group, groups, host.id).await;
+            // Add group from filename
+            if let Some(value) = add_grp_from_filename(db, &group, &groups, &host.id).await {
+                return value;
             }
+
+            // Add tags from config file
+            add_tags(db, &tags, &host.id).await;
+
+            // Add groups from config file
+            add_grps(db, &groups, &host.id).await;
+
+            // remove tags and groups that are missing in config file.
+            remove_unused_tags(db, tags, &host.id).await;
+
+            remove_unused_grps(db, &group, groups, host.id).await;
         }
     }
     Ok(())
 }
 
+/// Finds aliases of `host` which were defined earlier, either read during this
+/// ingest or stored from a different file which still defines them.
+async fn earlier_definitions<C: Connection>(
+    db: &Surreal<C>,
+    host: &Host,
+    source: &HostSource,
+    seen: &HashMap<String, HostSource>,
+) -> Result<Vec<(String, HostSource)>> {
+    let mut earlier = vec![];
+    for alias in &host.aliases {
+        if let Some(kept) = seen.get(&alias.to_lowercase()) {
+            earlier.push((alias.clone(), kept.clone()));
+            continue;
+        }
+
+        let existing = EnhancedHost::get_host_by_alias(db, alias.clone())
+            .await
+            .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+        if let Some(record) = existing {
+            if let Some(kept) = record.source {
+                if kept.path != source.path && kept.defines(&record.host) {
+                    earlier.push((alias.clone(), kept));
+                }
+            }
+        }
+    }
+
+    Ok(earlier)
+}
+
 async fn remove_unused_grps<C: Connection>(
     db: &Surreal<C>,
     group: &Option<String>,
@@ -103,7 +384,7 @@ async fn remove_unused_grps<C: Connection>(
 ) {
     for grp in EnhancedHost::get_groups(db, &host).await.unwrap() {
         if let Some(group) = group.clone() {
-            if !groups.contains(&grp.0) && grp.1.name.to_lowercase() != group {
+            if !groups.contains(&grp.0) && Group::key(&grp.1.name) != Group::key(&group) {
                 EnhancedHost::remove_group(db, &host, &grp.0).await.unwrap();
             }
         }
@@ -143,7 +424,7 @@ async fn add_grp_from_filename<C: Connection>(
     host: &Thing,
 ) -> Option<std::result::Result<(), Error>> {
     if let Some(grp) = group.clone() {
-        let record_id: Thing = match Group::create_or_update(grp.clone(), db).await {
+        let record_id: Thing = match Group::create_path(db, &grp).await {
             Ok(t) => t,
             Err(e) => return Some(Err(Error::new(ErrorKind::Other, e.to_string()))),
         };
@@ -158,48 +439,455 @@ async fn add_grp_from_filename<C: Connection>(
     None
 }
 
+/// Metadata read from comments above a stanza.
+#[derive(Debug, Default)]
+struct StanzaMetadata {
+    groups: Vec<Thing>,
+    tags: Vec<Thing>,
+    comment: Option<String>,
+    attributes: Attributes,
+}
+
 async fn extract_metadata<C: Connection>(
-    lines: &mut Vec<&str>,
-    groups: &mut Vec<Thing>,
-    tags: &mut Vec<Thing>,
-    comment: &mut Option<String>,
+    stanza: &Stanza,
+    file: &SourceFile,
     db: &Surreal<C>,
-) {
-    while let Some(line) = lines.first() {
+    diagnostics: &mut Vec<Diagnostic>,
+) -> StanzaMetadata {
+    let mut metadata = StanzaMetadata::default();
+
+    // Metadata lines are consecutive and directly above the header
+    for (number, line) in (stanza.start..).zip(&stanza.metadata) {
         if line.starts_with("#--(") {
             // Parse groups only if present
             if let Some(group_str) = line.strip_prefix("#--(").and_then(|s| s.strip_suffix(")")) {
-                let group_names: Vec<String> =
-                    group_str.split(',').map(|s| s.trim().to_string()).collect();
-
-                for group_name in group_names {
-                    if let Ok(group_id) = Group::create_or_update(group_name, db).await {
-                        groups.push(group_id);
+                for group_name in metadata_names(group_str, file, number, diagnostics) {
+                    // `a/b/c` declares `c` below `b` below `a`
+                    if let Ok(group_id) = Group::create_path(db, &group_name).await {
+                        metadata.groups.push(group_id);
                     }
                 }
+            } else {
+                diagnostics.push(malformed_metadata(file, number, "`)`"));
             }
-            lines.remove(0);
         } else if line.starts_with("#--[") {
             // Parse tags only if present
             if let Some(tag_str) = line.strip_prefix("#--[").and_then(|s| s.strip_suffix("]")) {
-                let tag_names: Vec<String> =
-                    tag_str.split(',').map(|s| s.trim().to_string()).collect();
-
-                for tag_name in tag_names {
+                for tag_name in metadata_names(tag_str, file, number, diagnostics) {
                     if let Ok(tag_id) = Tag::create_or_update(tag_name, db).await {
-                        tags.push(tag_id);
+                        metadata.tags.push(tag_id);
                     }
                 }
+            } else {
+                diagnostics.push(malformed_metadata(file, number, "`]`"));
+            }
+        } else if line.starts_with("#--{") {
+            // Later attribute comments override earlier ones with the same key
+            match line.strip_prefix("#--{").and_then(|s| s.strip_suffix("}")) {
+                Some(list) => match parse_attributes(list) {
+                    Ok(attributes) => metadata.attributes.extend(attributes),
+                    Err(message) => diagnostics.push(Diagnostic::warning(
+                        &file.path,
+                        number,
+                        columns(file, number).0,
+                        message,
+                    )),
+                },
+                None => diagnostics.push(malformed_metadata(file, number, "`}`")),
+            }
+        } else if line.starts_with("#--") {
+            diagnostics.push(Diagnostic::warning(
+                &file.path,
+                number,
+                columns(file, number).0,
+                "unknown metadata comment, expected `#--(groups)`, `#--[tags]` or `#--{key=value}`",
+            ));
+        } else if line.starts_with("# ") || line == "#" {
+            // Every comment line is kept, bare `#` separates paragraphs
+            let text = tokenizer::comment_text(line);
+            match &mut metadata.comment {
+                Some(comment) => {
+                    comment.push('\n');
+                    comment.push_str(text);
+                }
+                None => metadata.comment = Some(text.to_string()),
+            }
+        }
+    }
+
+    metadata
+}
+
+/// Comma separated group or tag names, empty ones are reported and skipped.
+fn metadata_names(
+    list: &str,
+    file: &SourceFile,
+    number: usize,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<String> {
+    let names: Vec<String> = list.split(',').map(|s| s.trim().to_string()).collect();
+
+    if names.iter().any(String::is_empty) {
+        diagnostics.push(Diagnostic::warning(
+            &file.path,
+            number,
+            columns(file, number).0,
+            "empty name in metadata comment",
+        ));
+    }
+
+    names.into_iter().filter(|name| !name.is_empty()).collect()
+}
+
+fn malformed_metadata(file: &SourceFile, number: usize, expected: &str) -> Diagnostic {
+    let line = file.content.lines().nth(number - 1).unwrap_or_default();
+
+    Diagnostic::warning(
+        &file.path,
+        number,
+        line.trim_end().len() + 1,
+        format!("malformed metadata comment, expected {}", expected),
+    )
+}
+
+/// Parses `Host` stanza.
+///
+/// Options ssh2-config rejects are found by parsing them one by one. Unknown ones
+/// are reported as warnings and kept, options with invalid values are reported as
+/// errors and left out, so the rest of the host is still stored.
+fn parse_host(
+    stanza: &Stanza,
+    file: &SourceFile,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<Host> {
+    let mut stanza = stanza.clone();
+
+    if parse_stanza(&stanza, ParseRule::STRICT).is_err() {
+        let header = Stanza {
+            directives: vec![],
+            ..stanza.clone()
+        };
+
+        stanza.directives.retain(|directive| {
+            let single = Stanza {
+                directives: vec![directive.clone()],
+                ..header.clone()
+            };
+            let (keyword, value) = columns(file, directive.line);
+
+            match parse_stanza(&single, ParseRule::STRICT) {
+                Ok(_) | Err(SshParserError::UnsupportedField(..)) => true,
+                Err(SshParserError::UnknownField(..)) => {
+                    diagnostics.push(Diagnostic::warning(
+                        &file.path,
+                        directive.line,
+                        keyword,
+                        format!("unknown directive `{}`", directive.keyword),
+                    ));
+                    true
+                }
+                Err(e) => {
+                    diagnostics.push(Diagnostic::error(
+                        &file.path,
+                        directive.line,
+                        value,
+                        format!("invalid value of `{}`: {}", directive.keyword, e),
+                    ));
+                    false
+                }
             }
-            lines.remove(0);
-        } else if line.starts_with("# ") {
-            *comment = Some(line[2..].to_string());
-            lines.remove(0);
-        } else {
-            break;
+        });
+    }
+
+    let rule = ParseRule::ALLOW_UNKNOWN_FIELDS | ParseRule::ALLOW_UNSUPPORTED_FIELDS;
+    match parse_stanza(&stanza, rule) {
+        Ok(config) => config.get_hosts().get(1).cloned().map(Host::from),
+        Err(e) => {
+            let line = stanza
+                .header
+                .as_ref()
+                .map(|h| h.line)
+                .unwrap_or(stanza.start);
+            diagnostics.push(Diagnostic::error(
+                &file.path,
+                line,
+                columns(file, line).1,
+                format!("host is ignored: {}", e),
+            ));
+            None
         }
     }
 }
 
+fn parse_stanza(
+    stanza: &Stanza,
+    rule: ParseRule,
+) -> std::result::Result<SshConfig, SshParserError> {
+    let config = stanza.to_string();
+    SshConfig::default().parse(&mut config.as_bytes(), rule)
+}
+
+/// 1-based columns of keyword and of the first argument on line `number`.
+fn columns(file: &SourceFile, number: usize) -> (usize, usize) {
+    let line = file.content.lines().nth(number - 1).unwrap_or_default();
+    let content = line.trim_start();
+    let keyword = line.len() - content.len();
+
+    let keyword_end = content
+        .find(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or(content.len());
+    let rest = &content[keyword_end..];
+    let value =
+        keyword + keyword_end + rest.len() - rest.trim_start_matches([' ', '\t', '=']).len();
+
+    (keyword + 1, value + 1)
+}
+
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use cst::ConfigFiles;
+    use db::memory_db;
+    use diagnostics::Severity;
+    use std::fs;
+    use tempdir::TempDir;
+
+    fn source_file(content: &str) -> SourceFile {
+        SourceFile {
+            path: PathBuf::from("config"),
+            group: None,
+            content: content.to_string(),
+            origin: None,
+            includes: vec![],
+        }
+    }
+
+    #[test]
+    fn test_columns() {
+        let file = source_file("Host a\n    Port = 22\n\tUser\tme\n");
+
+        assert_eq!(columns(&file, 1), (1, 6));
+        assert_eq!(columns(&file, 2), (5, 12));
+        assert_eq!(columns(&file, 3), (2, 7));
+    }
+
+    #[test]
+    fn test_parse_host_reports_bad_options() {
+        let file = source_file("Host a\n    HostName 10.0.0.1\n    Hostnme x\n    Port abc\n");
+        let stanza = tokenizer::tokenize(&file.content).remove(0);
+        let mut diagnostics = vec![];
+
+        let host = parse_host(&stanza, &file, &mut diagnostics).unwrap();
+
+        assert_eq!(host.host_name.as_deref(), Some("10.0.0.1"));
+        assert_eq!(host.port, None);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (3, 5));
+        assert_eq!(diagnostics[1].severity, Severity::Error);
+        assert_eq!((diagnostics[1].line, diagnostics[1].column), (4, 10));
+    }
+
+    #[test]
+    fn test_groupname_from_path() {
+        let base = glob_base(Path::new("/home/me/.ssh/config.d/**/*"));
+        assert_eq!(base, Path::new("/home/me/.ssh/config.d"));
+
+        assert_eq!(
+            groupname_from_path(
+                Path::new("/home/me/.ssh/config.d/teams/payments/prod"),
+                &base
+            ),
+            Some(String::from("teams/payments/prod"))
+        );
+        assert_eq!(
+            groupname_from_path(Path::new("/home/me/.ssh/config.d/work"), &base),
+            Some(String::from("work"))
+        );
+        assert_eq!(
+            groupname_from_path(Path::new("/etc/ssh/ssh_config"), &base),
+            Some(String::from("ssh_config"))
+        );
+        assert_eq!(
+            glob_base(Path::new("/home/me/.ssh/config")),
+            Path::new("/home/me/.ssh")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_comments_round_trip() {
+        let dir = TempDir::new("comments").unwrap();
+        let db = memory_db().await.unwrap();
+
+        let stanza = "\
+# Legacy billing box
+#
+# Do not upgrade before Q3
+Host billing
+    HostName 10.0.0.5
+    # appliance only speaks CBC
+    Ciphers aes128-cbc
+";
+        let config = dir.path().join("config");
+        fs::write(&config, stanza).unwrap();
+        Hosts::parse_config(&db, config, None, None).await.unwrap();
+
+        let record = EnhancedHost::get_host_by_name(&db, String::from("billing"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            record.comment.as_deref(),
+            Some("Legacy billing box\n\nDo not upgrade before Q3")
+        );
+        assert_eq!(
+            record.option_comments["ciphers"],
+            "appliance only speaks CBC"
+        );
+        assert_eq!(EnhancedHost::render(&db, &record).await.unwrap(), stanza);
+    }
+
+    #[tokio::test]
+    async fn test_only_colliding_aliases_are_skipped() {
+        let dir = TempDir::new("duplicates").unwrap();
+        let db = memory_db().await.unwrap();
+
+        let config = dir.path().join("config");
+        fs::write(
+            &config,
+            "Host b\n    HostName one\n\nHost a b\n    HostName two\n",
+        )
+        .unwrap();
+        let report = Hosts::parse_config(&db, config, None, None).await.unwrap();
+
+        assert_eq!(report.duplicates.len(), 1);
+        assert_eq!(report.duplicates[0].name, "b");
+        assert_eq!(report.diagnostics.len(), 1);
+        assert_eq!(report.diagnostics[0].line, 4);
+
+        let b = EnhancedHost::get_host_by_name(&db, String::from("b"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(b.host.host_name.as_deref(), Some("one"));
+        let a = EnhancedHost::get_host_by_name(&db, String::from("a"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(a.host.host_name.as_deref(), Some("two"));
+        assert_eq!(a.host.aliases, ["a"]);
+    }
+
+    #[tokio::test]
+    async fn test_describe_lists_applying_rules() {
+        let dir = TempDir::new("rules").unwrap();
+        let db = memory_db().await.unwrap();
+
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        fs::write(
+            &a,
+            "Host web\n    HostName 10.0.0.1\n    IdentityFile %d/.ssh/id_%h\n\nMatch host 10.0.0.*\n    User deploy\n",
+        )
+        .unwrap();
+        fs::write(
+            &b,
+            "Match host 10.0.0.*\n    User ops\n\nMatch host *.dev\n    User dev\n",
+        )
+        .unwrap();
+        Hosts::parse_config(&db, a, None, None).await.unwrap();
+        Hosts::parse_config(&db, b, None, None).await.unwrap();
+
+        // Same `Match` line in another file is a rule of its own
+        assert_eq!(EnhancedRule::get_all(&db).await.unwrap().len(), 3);
+
+        let described = Hosts::describe(&db, "web").await.unwrap().unwrap();
+        assert!(described.starts_with("Host web\n"));
+        assert!(described.contains("    IdentityFile %d/.ssh/id_%h\n"));
+        assert!(described.contains("# %d/.ssh/id_%h is "));
+        assert!(described.contains("/.ssh/id_10.0.0.1\n"));
+        assert!(described.contains("# applies, "));
+        assert!(described.contains("    user deploy\n"));
+        assert!(described.contains("    user ops\n"));
+        assert!(!described.contains("user dev"));
+        assert!(Hosts::describe(&db, "missing").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_source_defaults_merge_with_metadata() {
+        let dir = TempDir::new("sources").unwrap();
+        let db = memory_db().await.unwrap();
+
+        let config = dir.path().join("config");
+        fs::write(&config, "#--[db]\nHost billing\n    HostName 10.0.0.5\n").unwrap();
+        let origin = Source {
+            name: Some(String::from("infra")),
+            path: config.to_string_lossy().to_string(),
+            group: Some(String::from("infra")),
+            tags: vec![String::from("team")],
+            read_only: true,
+            ..Default::default()
+        };
+        Hosts::parse_config(&db, config.clone(), None, Some(&origin))
+            .await
+            .unwrap();
+
+        let record = EnhancedHost::get_host_by_name(&db, String::from("billing"))
+            .await
+            .unwrap()
+            .unwrap();
+        let source = record.source.as_ref().unwrap();
+        assert_eq!(source.origin.as_deref(), Some("infra"));
+        assert!(source.read_only);
+
+        let mut tags: Vec<String> = EnhancedHost::get_tags(&db, &record.id)
+            .await
+            .unwrap()
+            .into_values()
+            .map(|t| t.name)
+            .collect();
+        tags.sort();
+        assert_eq!(tags, ["db", "team"]);
+        let groups: Vec<String> = EnhancedHost::get_groups(&db, &record.id)
+            .await
+            .unwrap()
+            .into_values()
+            .map(|g| g.name)
+            .collect();
+        assert_eq!(groups, ["infra"]);
+
+        let mut files = ConfigFiles::load(config).unwrap();
+        assert!(
+            EnhancedHost::write_back(&db, &record, "billing", &mut files)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mixed_case_groups_are_kept() {
+        let dir = TempDir::new("groups").unwrap();
+        let db = memory_db().await.unwrap();
+
+        let config = dir.path().join("config");
+        fs::write(&config, "#--(Infra/DB)\nHost pg1\n    HostName 10.0.0.5\n").unwrap();
+        for _ in 0..2 {
+            Hosts::parse_config(&db, config.clone(), Some(String::from("Ops")), None)
+                .await
+                .unwrap();
+        }
+
+        let record = EnhancedHost::get_host_by_name(&db, String::from("pg1"))
+            .await
+            .unwrap()
+            .unwrap();
+        let mut groups: Vec<String> = EnhancedHost::get_groups(&db, &record.id)
+            .await
+            .unwrap()
+            .into_values()
+            .map(|g| g.name)
+            .collect();
+        groups.sort();
+        assert_eq!(groups, ["Infra/DB", "Ops"]);
+    }
+}
@@ -0,0 +1,416 @@
+use std::{
+    collections::HashSet,
+    fs,
+    io::Result,
+    path::{Path, PathBuf},
+};
+
+use config::{expand, Source};
+use diagnostics::Diagnostic;
+
+use crate::tokenizer::{self, Stanza};
+
+/// Maximum nesting of `Include` directives, same limit as OpenSSH uses.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Warning for `Include` at `line` of `path`, when `path` is itself included
+/// through `depth` files and its includes are nested too deep to be followed.
+pub(crate) fn depth_exceeded(depth: usize, path: &Path, line: usize) -> Option<Diagnostic> {
+    (depth >= MAX_INCLUDE_DEPTH).then(|| {
+        Diagnostic::warning(
+            path,
+            line,
+            0,
+            format!(
+                "includes are nested deeper than {}, they are ignored",
+                MAX_INCLUDE_DEPTH
+            ),
+        )
+    })
+}
+
+/// Single ssh config file reached from the root config, either directly or through `Include`.
+#[derive(Debug)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    /// Group derived from location of the file.
+    pub group: Option<String>,
+    pub content: String,
+    /// Configured source the file belongs to.
+    pub origin: Option<Source>,
+    /// Files first read through this one, as line of `Include` and index in collected files.
+    pub includes: Vec<(usize, usize)>,
+}
+
+impl SourceFile {
+    /// Group of hosts in this file, group derived from its location is nested
+    /// below group of its source.
+    pub fn full_group(&self) -> Option<String> {
+        let parent = self.origin.as_ref().and_then(|o| o.group.clone());
+        match (parent, &self.group) {
+            (Some(parent), Some(group)) => Some(format!("{}/{}", parent, group)),
+            (parent, group) => parent.or_else(|| group.clone()),
+        }
+    }
+}
+
+/// Follows `Include` directives the same way OpenSSH does.
+///
+/// Relative include paths are resolved against `ssh_dir` (`~/.ssh` by default),
+/// `~` and environment variables are expanded and every argument is treated as a glob.
+#[derive(Debug)]
+pub struct IncludeResolver {
+    ssh_dir: PathBuf,
+    origin: Option<Source>,
+}
+
+impl Default for IncludeResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IncludeResolver {
+    pub fn new() -> Self {
+        Self {
+            ssh_dir: dirs::home_dir().unwrap_or_default().join(".ssh"),
+            origin: None,
+        }
+    }
+
+    pub fn with_ssh_dir(ssh_dir: PathBuf) -> Self {
+        Self {
+            ssh_dir,
+            origin: None,
+        }
+    }
+
+    /// Files read by this resolver, included ones too, belong to `origin`.
+    pub fn with_origin(mut self, origin: Option<Source>) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    /// Reads `path` and every file it includes, depth first.
+    ///
+    /// Included files get their group from their path below the directory of the
+    /// `Include` pattern, see [`crate::groupname_from_path`]. Files that were already
+    /// read are skipped, which also breaks include cycles.
+    ///
+    /// Only unreadable `path` is an error, unreadable or looping includes are skipped
+    /// like OpenSSH does and reported in `diagnostics` at their `Include` line. Ingest
+    /// shows them to the user, watching and editing only need the readable files.
+    pub fn collect(
+        &self,
+        path: PathBuf,
+        group: Option<String>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Result<Vec<SourceFile>> {
+        let mut sources = Vec::new();
+        let mut seen = HashSet::new();
+
+        self.visit(
+            path,
+            group,
+            &mut vec![],
+            &mut seen,
+            &mut sources,
+            diagnostics,
+        )?;
+
+        Ok(sources)
+    }
+
+    /// Reads `path` and files it includes, `chain` holds files currently being read.
+    fn visit(
+        &self,
+        path: PathBuf,
+        group: Option<String>,
+        chain: &mut Vec<PathBuf>,
+        seen: &mut HashSet<PathBuf>,
+        sources: &mut Vec<SourceFile>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Result<()> {
+        let key = canonical(&path);
+        if !seen.insert(key.clone()) {
+            return Ok(());
+        }
+
+        let content = fs::read_to_string(&path)?;
+        let includes: Vec<(usize, String)> = content
+            .lines()
+            .enumerate()
+            .filter_map(|(index, line)| Some((index + 1, parse_include(line)?)))
+            .flat_map(|(line, patterns)| patterns.into_iter().map(move |p| (line, p)))
+            .collect();
+
+        let index = sources.len();
+        sources.push(SourceFile {
+            path: path.clone(),
+            group,
+            content,
+            origin: self.origin.clone(),
+            includes: vec![],
+        });
+
+        if includes.is_empty() {
+            return Ok(());
+        }
+        if let Some(diagnostic) = depth_exceeded(chain.len(), &path, includes[0].0) {
+            diagnostics.push(diagnostic);
+            return Ok(());
+        }
+
+        chain.push(key);
+        for (line, pattern) in includes {
+            let base = crate::glob_base(&self.expand(&pattern));
+            for included in self.resolve(&pattern) {
+                if chain.contains(&canonical(&included)) {
+                    diagnostics.push(Diagnostic::warning(
+                        &path,
+                        line,
+                        0,
+                        format!(
+                            "`{}` includes a file which is already being read, include loop is ignored",
+                            included.display()
+                        ),
+                    ));
+                    continue;
+                }
+
+                let group = crate::groupname_from_path(&included, &base);
+                let next = sources.len();
+                // Included files may be unreadable (e.g. sockets or permissions), OpenSSH skips them too
+                match self.visit(included.clone(), group, chain, seen, sources, diagnostics) {
+                    Ok(()) if sources.len() > next => sources[index].includes.push((line, next)),
+                    Ok(()) => {}
+                    Err(e) => diagnostics.push(Diagnostic::warning(
+                        &path,
+                        line,
+                        0,
+                        format!("can't read included `{}`: {}", included.display(), e),
+                    )),
+                }
+            }
+        }
+        chain.pop();
+
+        Ok(())
+    }
+
+    /// Expands a single `Include` argument into existing files, sorted like glob(3).
+    pub fn resolve(&self, pattern: &str) -> Vec<PathBuf> {
+        let path = self.expand(pattern);
+        let Some(pattern) = path.to_str() else {
+            return vec![];
+        };
+
+        match glob::glob(pattern) {
+            Ok(paths) => paths
+                .filter_map(|p| p.ok())
+                .filter(|p| p.is_file())
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    /// `Include` argument as absolute path, `~`, environment variables and local
+    /// `%` tokens expanded and relative paths resolved against ssh directory.
+    pub fn expand(&self, pattern: &str) -> PathBuf {
+        let path = expand::expand_path(pattern);
+
+        if path.is_absolute() {
+            path
+        } else {
+            self.ssh_dir.join(path)
+        }
+    }
+}
+
+/// Stanzas of `sources` returned by [`IncludeResolver::collect`] in the order ssh
+/// reads them, stanzas of included files follow the stanza with their `Include`.
+///
+/// Consecutive stanzas of one file are returned together with index of the file.
+pub fn read_order(sources: &[SourceFile]) -> Vec<(usize, Vec<Stanza>)> {
+    let mut order = vec![];
+    if !sources.is_empty() {
+        visit_stanzas(sources, 0, &mut order);
+    }
+    order
+}
+
+fn visit_stanzas(sources: &[SourceFile], index: usize, order: &mut Vec<(usize, Vec<Stanza>)>) {
+    let mut includes = sources[index].includes.iter().peekable();
+    let mut stanzas = vec![];
+
+    for stanza in tokenizer::tokenize(&sources[index].content) {
+        let end = stanza.end;
+        stanzas.push(stanza);
+        while let Some((_, included)) = includes.next_if(|(line, _)| *line <= end) {
+            if !stanzas.is_empty() {
+                order.push((index, std::mem::take(&mut stanzas)));
+            }
+            visit_stanzas(sources, *included, order);
+        }
+    }
+    if !stanzas.is_empty() {
+        order.push((index, stanzas));
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Returns arguments of an `Include` directive, or `None` when the line is something else.
+pub fn parse_include(line: &str) -> Option<Vec<String>> {
+    tokenizer::parse_line(line, 0)
+        .filter(|directive| directive.is("include"))
+        .map(|directive| directive.args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_parse_include() {
+        assert_eq!(
+            parse_include("Include config.d/*"),
+            Some(vec!["config.d/*".to_string()])
+        );
+        assert_eq!(
+            parse_include("  include=\"with space\" other"),
+            Some(vec!["with space".to_string(), "other".to_string()])
+        );
+        assert_eq!(parse_include("IncludeSomething x"), None);
+        assert_eq!(parse_include("Host include"), None);
+    }
+
+    #[test]
+    fn test_collect_follows_relative_globs() {
+        let dir = TempDir::new("include").unwrap();
+        fs::create_dir(dir.path().join("config.d")).unwrap();
+        fs::write(dir.path().join("config"), "Include config.d/*\n").unwrap();
+        fs::write(dir.path().join("config.d/work"), "Host work\n").unwrap();
+        fs::write(dir.path().join("config.d/home"), "Host home\n").unwrap();
+
+        let resolver = IncludeResolver::with_ssh_dir(dir.path().to_path_buf());
+        let sources = resolver
+            .collect(dir.path().join("config"), None, &mut vec![])
+            .unwrap();
+
+        let groups: Vec<Option<String>> = sources.iter().map(|s| s.group.clone()).collect();
+        assert_eq!(
+            groups,
+            vec![None, Some("home".to_string()), Some("work".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_nested_directories_become_group_path() {
+        let dir = TempDir::new("include").unwrap();
+        fs::create_dir_all(dir.path().join("config.d/teams/payments")).unwrap();
+        fs::write(dir.path().join("config"), "Include config.d/**/*\n").unwrap();
+        fs::write(dir.path().join("config.d/teams/payments/prod"), "Host p\n").unwrap();
+
+        let resolver = IncludeResolver::with_ssh_dir(dir.path().to_path_buf());
+        let sources = resolver
+            .collect(dir.path().join("config"), None, &mut vec![])
+            .unwrap();
+
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[1].group.as_deref(), Some("teams/payments/prod"));
+
+        // Source group is the parent of directory groups
+        let origin = Source {
+            group: Some(String::from("infra")),
+            ..Default::default()
+        };
+        let resolver =
+            IncludeResolver::with_ssh_dir(dir.path().to_path_buf()).with_origin(Some(origin));
+        let sources = resolver
+            .collect(dir.path().join("config"), None, &mut vec![])
+            .unwrap();
+        assert_eq!(sources[0].full_group().as_deref(), Some("infra"));
+        assert_eq!(
+            sources[1].full_group().as_deref(),
+            Some("infra/teams/payments/prod")
+        );
+    }
+
+    #[test]
+    fn test_included_stanzas_are_read_in_place() {
+        let dir = TempDir::new("include").unwrap();
+        fs::write(
+            dir.path().join("config"),
+            "Host a\n  Include extra\n  User x\nHost b\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("extra"), "Host c\n").unwrap();
+
+        let resolver = IncludeResolver::with_ssh_dir(dir.path().to_path_buf());
+        let sources = resolver
+            .collect(dir.path().join("config"), None, &mut vec![])
+            .unwrap();
+
+        let hosts: Vec<(usize, String)> = read_order(&sources)
+            .into_iter()
+            .flat_map(|(index, stanzas)| stanzas.into_iter().map(move |s| (index, s)))
+            .filter_map(|(index, s)| Some((index, s.header?.args.join(" "))))
+            .collect();
+        assert_eq!(
+            hosts,
+            [
+                (0, String::from("a")),
+                (1, String::from("c")),
+                (0, String::from("b"))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collect_breaks_cycles() {
+        let dir = TempDir::new("include").unwrap();
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        fs::write(&a, format!("Include {}\n", b.display())).unwrap();
+        fs::write(&b, format!("Include {}\n", a.display())).unwrap();
+
+        let resolver = IncludeResolver::with_ssh_dir(dir.path().to_path_buf());
+        let mut diagnostics = vec![];
+        let sources = resolver.collect(a, None, &mut diagnostics).unwrap();
+
+        assert_eq!(sources.len(), 2);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            (diagnostics[0].path.as_path(), diagnostics[0].line),
+            (b.as_path(), 1)
+        );
+    }
+
+    #[test]
+    fn test_unreadable_include_is_reported() {
+        let dir = TempDir::new("include").unwrap();
+        fs::create_dir(dir.path().join("config.d")).unwrap();
+        fs::write(
+            dir.path().join("config"),
+            "Host a\nInclude config.d\nInclude config.d/*\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("config.d/work"), [0xff, 0xfe]).unwrap();
+
+        let resolver = IncludeResolver::with_ssh_dir(dir.path().to_path_buf());
+        let mut diagnostics = vec![];
+        let sources = resolver
+            .collect(dir.path().join("config"), None, &mut diagnostics)
+            .unwrap();
+
+        // Directory isn't a file to include, invalid UTF-8 file is unreadable
+        assert_eq!(sources.len(), 1);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, 3);
+        assert!(diagnostics[0].message.contains("work"));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::host::{
+    attribute::AttributeFilter,
     table::{Group, Tag},
     HostRecord,
 };
@@ -35,7 +36,10 @@ impl HostSearch {
 
         // Find matching hosts
         let hosts: Vec<HostRecord> = db
-            .query("SELECT * FROM host WHERE string::lowercase(host.name) CONTAINS $pattern")
+            .query(
+                "SELECT * FROM host WHERE string::lowercase(host.name) CONTAINS $pattern \
+                OR string::lowercase(array::join(host.aliases ?? [], ' ')) CONTAINS $pattern",
+            )
             .bind(("pattern", pattern.clone()))
             .await?
             .take(0)?;
@@ -47,9 +51,13 @@ impl HostSearch {
             .await?
             .take(0)?;
 
-        // Find matching groups
+        // Find matching groups by their own name, returned with full path
         let groups: Vec<Group> = db
-            .query("SELECT name FROM group WHERE string::lowercase(name) CONTAINS $pattern")
+            .query(
+                "SELECT name FROM group \
+                WHERE string::lowercase(array::last(string::split(name, '/'))) CONTAINS $pattern \
+                ORDER BY name",
+            )
             .bind(("pattern", pattern.clone()))
             .await?
             .take(0)?;
@@ -62,13 +70,23 @@ impl HostSearch {
     }
 
     /// Get hosts by selected criteria
+    ///
+    /// With `include_descendants` hosts of groups below selected ones match as well.
     pub async fn get_filtered_hosts<C: Connection>(
         db: &Surreal<C>,
         selected_groups: Vec<String>,
         selected_tags: Vec<String>,
+        attributes: Vec<AttributeFilter>,
+        include_descendants: bool,
     ) -> Result<Vec<HostRecord>, Error> {
         let mut query = String::from("SELECT * FROM host WHERE 1=1");
 
+        let selected_groups = if include_descendants {
+            Group::with_descendants(db, selected_groups).await?
+        } else {
+            selected_groups
+        };
+
         // Add group filters
         if !selected_groups.is_empty() {
             query.push_str(" AND id IN (SELECT VALUE out FROM groupped WHERE in IN (SELECT VALUE id FROM group WHERE name IN $groups))");
@@ -79,12 +97,24 @@ impl HostSearch {
             query.push_str(" AND id IN (SELECT VALUE out FROM tagged WHERE in IN (SELECT VALUE id FROM tag WHERE name IN $tags))");
         }
 
-        let hosts: Vec<HostRecord> = db
+        // Add attribute filters, keys are validated when filter is parsed
+        for (i, filter) in attributes.iter().enumerate() {
+            query.push_str(&format!(
+                " AND attributes.`{}` {} $attr{}",
+                filter.key,
+                filter.comparison.operator(),
+                i
+            ));
+        }
+
+        let mut request = db
             .query(query)
             .bind(("groups", selected_groups))
-            .bind(("tags", selected_tags))
-            .await?
-            .take(0)?;
+            .bind(("tags", selected_tags));
+        for (i, filter) in attributes.into_iter().enumerate() {
+            request = request.bind((format!("attr{}", i), filter.value));
+        }
+        let hosts: Vec<HostRecord> = request.await?.take(0)?;
 
         Ok(hosts)
     }
@@ -96,13 +126,14 @@ mod tests {
 
     use super::*;
     use crate::host::{
+        attribute::parse_attributes,
+        pattern::HostPattern,
         table::{Group, Tag},
         EnhancedHost, Host,
     };
-    use db::define_schema;
+    use db::memory_db;
     // use serde::{Deserialize, Serialize};
-    use surrealdb::engine::local::{Db, RocksDb};
-    use tempdir::TempDir;
+    use surrealdb::engine::local::Db;
 
     async fn setup_test_data(db: &Surreal<Db>) -> Result<HostRecord, Error> {
         // Create tags
@@ -119,6 +150,8 @@ mod tests {
         let host_a = EnhancedHost {
             host: Host {
                 name: "A".to_string(),
+                patterns: vec![HostPattern::new("A")],
+                aliases: vec!["A".to_string()],
                 bind_address: None,
                 bind_interface: None,
                 ca_signature_algorithms: None,
@@ -147,6 +180,9 @@ mod tests {
                 use_keychain: None,
             },
             comment: None,
+            source: None,
+            attributes: Default::default(),
+            option_comments: Default::default(),
         };
         let host_a_record = EnhancedHost::create(db, host_a).await?;
         EnhancedHost::add_tag(db, &host_a_record.id, &tag_def).await?;
@@ -156,6 +192,8 @@ mod tests {
         let host_b = EnhancedHost {
             host: Host {
                 name: "B".to_string(),
+                patterns: vec![HostPattern::new("B")],
+                aliases: vec!["B".to_string()],
                 bind_address: None,
                 bind_interface: None,
                 ca_signature_algorithms: None,
@@ -184,6 +222,9 @@ mod tests {
                 use_keychain: None,
             },
             comment: None,
+            source: None,
+            attributes: Default::default(),
+            option_comments: Default::default(),
         };
         let host_b_record = EnhancedHost::create(db, host_b).await?;
         EnhancedHost::add_group(db, &host_b_record.id, &group_dev).await?;
@@ -192,6 +233,8 @@ mod tests {
         let host_d = EnhancedHost {
             host: Host {
                 name: "D".to_string(),
+                patterns: vec![HostPattern::new("D")],
+                aliases: vec!["D".to_string()],
                 bind_address: None,
                 bind_interface: None,
                 ca_signature_algorithms: None,
@@ -220,6 +263,9 @@ mod tests {
                 use_keychain: None,
             },
             comment: None,
+            source: None,
+            attributes: Default::default(),
+            option_comments: Default::default(),
         };
         let host_d_record = EnhancedHost::create(db, host_d).await?;
         EnhancedHost::add_tag(db, &host_d_record.id, &tag_abc).await?;
@@ -229,12 +275,184 @@ mod tests {
         Ok(host_d_record)
     }
 
+    #[tokio::test]
+    async fn test_search_by_alias() -> Result<(), Error> {
+        let db = memory_db().await.unwrap();
+
+        let host = EnhancedHost {
+            host: Host {
+                name: "web1".to_string(),
+                patterns: ["web1", "web2", "web3", "!bastion"]
+                    .into_iter()
+                    .map(HostPattern::new)
+                    .collect(),
+                aliases: vec!["web1".to_string(), "web2".to_string(), "web3".to_string()],
+                ..Default::default()
+            },
+            comment: None,
+            source: None,
+            attributes: Default::default(),
+            option_comments: Default::default(),
+        };
+        let record = EnhancedHost::create(&db, host).await?;
+        assert_eq!(record.host.aliases.len(), 3);
+        assert_eq!(record.host.patterns.len(), 4);
+
+        let suggestions = HostSearch::suggest(&db, "web3").await?;
+        assert!(suggestions.hosts.contains(&record));
+
+        let found = EnhancedHost::get_host_by_alias(&db, "web2".to_string()).await?;
+        assert_eq!(found, Some(record));
+
+        // Alias doesn't identify a host, `Host web2` is a host of its own
+        let web2 = EnhancedHost {
+            host: Host {
+                name: "web2".to_string(),
+                patterns: vec![HostPattern::new("web2")],
+                aliases: vec!["web2".to_string()],
+                user: Some("deploy".to_string()),
+                ..Default::default()
+            },
+            comment: None,
+            source: None,
+            attributes: Default::default(),
+            option_comments: Default::default(),
+        };
+        let web2 = EnhancedHost::create_or_update(&db, web2).await?;
+        assert_eq!(web2.host.name, "web2");
+        let web1 = EnhancedHost::get_host_by_name(&db, "web1".to_string()).await?;
+        assert_eq!(web1.unwrap().host.user, None);
+        let found = EnhancedHost::get_host_by_alias(&db, "web2".to_string()).await?;
+        assert_eq!(found, Some(web2));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_filter_by_attributes() -> Result<(), Error> {
+        let db = memory_db().await.unwrap();
+
+        for (name, attributes) in [
+            ("web", "env=prod, rotation=2, expires=2026-12-31"),
+            ("db", "env=prod, rotation=5, expires=2025-06-01"),
+            ("dev", "env=dev"),
+        ] {
+            EnhancedHost::create(
+                &db,
+                EnhancedHost {
+                    host: Host {
+                        name: name.to_string(),
+                        patterns: vec![HostPattern::new(name)],
+                        aliases: vec![name.to_string()],
+                        ..Default::default()
+                    },
+                    comment: None,
+                    source: None,
+                    attributes: parse_attributes(attributes).unwrap(),
+                    option_comments: Default::default(),
+                },
+            )
+            .await?;
+        }
+
+        let filters = |filters: &[&str]| -> Vec<AttributeFilter> {
+            filters.iter().map(|f| f.parse().unwrap()).collect()
+        };
+
+        let hosts =
+            HostSearch::get_filtered_hosts(&db, vec![], vec![], filters(&["env=prod"]), false)
+                .await?;
+        assert_eq!(hosts.len(), 2);
+
+        let hosts = HostSearch::get_filtered_hosts(
+            &db,
+            vec![],
+            vec![],
+            filters(&["env=prod", "rotation>3"]),
+            false,
+        )
+        .await?;
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].host.name, "db");
+
+        let hosts = HostSearch::get_filtered_hosts(
+            &db,
+            vec![],
+            vec![],
+            filters(&["expires<2026-01-01"]),
+            false,
+        )
+        .await?;
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].attributes["expires"].to_string(), "2025-06-01");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_filter_includes_descendants() -> Result<(), Error> {
+        let db = memory_db().await.unwrap();
+
+        for (name, group) in [
+            ("pay-db", "teams/payments/prod"),
+            ("pay-web", "teams/payments"),
+            ("search", "teams/search/prod"),
+        ] {
+            let host = EnhancedHost::create(
+                &db,
+                EnhancedHost {
+                    host: Host {
+                        name: name.to_string(),
+                        patterns: vec![HostPattern::new(name)],
+                        aliases: vec![name.to_string()],
+                        ..Default::default()
+                    },
+                    comment: None,
+                    source: None,
+                    attributes: Default::default(),
+                    option_comments: Default::default(),
+                },
+            )
+            .await?;
+            let group = Group::create_path(&db, group).await?;
+            EnhancedHost::add_group(&db, &host.id, &group).await?;
+        }
+
+        let payments = vec![String::from("teams/payments")];
+        let hosts =
+            HostSearch::get_filtered_hosts(&db, payments.clone(), vec![], vec![], false).await?;
+        assert_eq!(hosts.len(), 1);
+
+        let hosts = HostSearch::get_filtered_hosts(&db, payments, vec![], vec![], true).await?;
+        assert_eq!(hosts.len(), 2);
+        assert!(hosts.iter().any(|h| h.host.name == "pay-db"));
+
+        let hosts =
+            HostSearch::get_filtered_hosts(&db, vec![String::from("teams")], vec![], vec![], true)
+                .await?;
+        assert_eq!(hosts.len(), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_suggest_shows_group_path() -> Result<(), Error> {
+        let db = memory_db().await.unwrap();
+
+        Group::create_path(&db, "payments/prod").await?;
+        Group::create_path(&db, "search/prod").await?;
+
+        let suggestions = HostSearch::suggest(&db, "prod").await?;
+        let paths: Vec<&str> = suggestions.groups.iter().map(|g| g.name.as_str()).collect();
+        assert_eq!(paths, ["payments/prod", "search/prod"]);
+        assert_eq!(suggestions.groups[0].leaf(), "prod");
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_search_flow() -> Result<(), Error> {
-        let temp_dir = TempDir::new("db").unwrap();
-        let db = Surreal::new::<RocksDb>(temp_dir.path()).await.unwrap();
-        let _ = db.use_ns("test").use_db("test").await;
-        let _ = define_schema(&db).await;
+        let db = memory_db().await.unwrap();
 
         // Setup test data
         let host_d = setup_test_data(&db).await?;
@@ -255,7 +473,9 @@ mod tests {
         assert!(suggestions.groups.contains(&group));
 
         // // When user selects group "dev"
-        let hosts = HostSearch::get_filtered_hosts(&db, vec!["dev".to_string()], vec![]).await?;
+        let hosts =
+            HostSearch::get_filtered_hosts(&db, vec!["dev".to_string()], vec![], vec![], false)
+                .await?;
         // // Should contain hosts A and B
         assert_eq!(hosts.len(), 2);
         assert!(hosts.iter().any(|h| h.host.name == "A"));
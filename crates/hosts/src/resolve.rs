@@ -0,0 +1,502 @@
+use std::{
+    fmt::Display,
+    fs,
+    io::Result,
+    path::{Path, PathBuf},
+};
+
+use config::expand::{ExpandedPath, Tokens};
+use diagnostics::Diagnostic;
+
+use crate::{
+    host::{pattern, Host},
+    include::{self, IncludeResolver},
+    rule::{self, Applicability, MatchRule},
+    tokenizer::{self, Directive, Stanza},
+};
+
+/// Options which may be given multiple times, every occurrence is used.
+const MULTI_VALUE_OPTIONS: [&str; 7] = [
+    "identityfile",
+    "certificatefile",
+    "localforward",
+    "remoteforward",
+    "dynamicforward",
+    "sendenv",
+    "setenv",
+];
+
+/// Where the value of an effective option comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    File {
+        path: PathBuf,
+        /// 1-based line of the option.
+        line: usize,
+        /// Header of the stanza which set the value, e.g. `Host *.prod`.
+        stanza: String,
+    },
+    /// Nothing in config sets the option, ssh falls back to its default.
+    Default,
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::File { path, line, stanza } => {
+                write!(f, "{}:{} ({})", path.display(), line, stanza)
+            }
+            Origin::Default => write!(f, "default"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EffectiveOption {
+    /// Lowercase option keyword.
+    pub keyword: String,
+    pub args: Vec<String>,
+    pub origin: Origin,
+}
+
+/// Options ssh would use for a host, equivalent of `ssh -G <host>`.
+#[derive(Debug, Default)]
+pub struct EffectiveConfig {
+    pub host: String,
+    /// Options in order in which they were set.
+    pub options: Vec<EffectiveOption>,
+    /// `Match` lines which couldn't be evaluated statically and were skipped.
+    pub undetermined: Vec<Origin>,
+    /// Includes which couldn't be read and were skipped.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl EffectiveConfig {
+    /// First value of `keyword`, this is the one ssh uses.
+    pub fn get(&self, keyword: &str) -> Option<&EffectiveOption> {
+        self.options
+            .iter()
+            .find(|o| o.keyword.eq_ignore_ascii_case(keyword))
+    }
+
+    /// Every value of `keyword`, useful for options like `IdentityFile`.
+    pub fn get_all<'a>(&'a self, keyword: &'a str) -> impl Iterator<Item = &'a EffectiveOption> {
+        self.options
+            .iter()
+            .filter(move |o| o.keyword.eq_ignore_ascii_case(keyword))
+    }
+
+    /// Every value of path option `keyword`, e.g. `IdentityFile`, as written and with
+    /// `~`, environment variables and `%` tokens of resolved host expanded.
+    pub fn paths(&self, keyword: &str) -> Vec<ExpandedPath> {
+        let tokens = Tokens::remote(
+            self.value("hostname"),
+            self.value("user"),
+            self.value("port").and_then(|p| p.parse().ok()),
+        );
+
+        self.get_all(keyword)
+            .flat_map(|o| o.args.first())
+            .map(|raw| ExpandedPath::new(raw, &tokens))
+            .collect()
+    }
+
+    fn value(&self, keyword: &str) -> Option<String> {
+        self.get(keyword).and_then(|o| o.args.first().cloned())
+    }
+
+    fn set(&mut self, directive: &Directive, origin: Origin) {
+        let keyword = directive.keyword.to_lowercase();
+        if !MULTI_VALUE_OPTIONS.contains(&keyword.as_str()) && self.get(&keyword).is_some() {
+            return;
+        }
+
+        self.options.push(EffectiveOption {
+            keyword,
+            args: directive.args.clone(),
+            origin,
+        });
+    }
+
+    fn set_default(&mut self, keyword: &str, value: String) {
+        if self.get(keyword).is_none() {
+            self.options.push(EffectiveOption {
+                keyword: keyword.to_string(),
+                args: vec![value],
+                origin: Origin::Default,
+            });
+        }
+    }
+}
+
+/// Prints options in `ssh -G` format followed by their origin.
+impl Display for EffectiveConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for option in &self.options {
+            writeln!(
+                f,
+                "{} {}  # {}",
+                option.keyword,
+                option.args.join(" "),
+                option.origin
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Stanza from one of the ingested files.
+#[derive(Debug, Clone)]
+pub struct SourceStanza {
+    pub path: PathBuf,
+    pub stanza: Stanza,
+    /// Headers of stanzas containing the `Include` which pulled this stanza in.
+    /// Stanza is only active when all of them match.
+    pub enclosing: Vec<Directive>,
+}
+
+/// Computes effective options of hosts with OpenSSH's first-match-wins semantics.
+///
+/// Every ingested file is read in the order ssh reads it, `Include` directives are
+/// expanded in place.
+#[derive(Debug, Default)]
+pub struct Resolver {
+    stanzas: Vec<SourceStanza>,
+    includes: IncludeResolver,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Resolver {
+    pub fn new(includes: IncludeResolver) -> Self {
+        Self {
+            stanzas: vec![],
+            includes,
+            diagnostics: vec![],
+        }
+    }
+
+    /// Reads root config files in given order.
+    pub fn from_paths(paths: impl IntoIterator<Item = PathBuf>) -> Result<Self> {
+        let mut resolver = Self::default();
+        for path in paths {
+            resolver.add_file(&path)?;
+        }
+        Ok(resolver)
+    }
+
+    pub fn add_file(&mut self, path: &Path) -> Result<()> {
+        let mut chain = vec![];
+        self.visit(path, &[], &mut chain)
+    }
+
+    pub fn stanzas(&self) -> &[SourceStanza] {
+        &self.stanzas
+    }
+
+    /// Includes skipped while reading files, reported at their `Include` line.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    fn visit(
+        &mut self,
+        path: &Path,
+        enclosing: &[Directive],
+        chain: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        let key = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if chain.contains(&key) {
+            return Ok(());
+        }
+        let depth = chain.len();
+
+        let content = fs::read_to_string(path)?;
+        chain.push(key);
+
+        for stanza in tokenizer::tokenize(&content) {
+            let mut inner = enclosing.to_vec();
+            inner.extend(stanza.header.clone());
+
+            let mut part = Stanza {
+                directives: vec![],
+                ..stanza.clone()
+            };
+
+            for directive in stanza.directives {
+                if !directive.is("include") {
+                    part.directives.push(directive);
+                    continue;
+                }
+
+                // Directives before `Include` apply before included files
+                self.push(path, part.clone(), enclosing);
+                part.directives.clear();
+
+                if let Some(diagnostic) = include::depth_exceeded(depth, path, directive.line) {
+                    self.diagnostics.push(diagnostic);
+                    continue;
+                }
+
+                for arg in &directive.args {
+                    for included in self.includes.resolve(arg) {
+                        // Unreadable included files are skipped, the same as ssh does.
+                        if let Err(e) = self.visit(&included, &inner, chain) {
+                            self.diagnostics.push(Diagnostic::warning(
+                                path,
+                                directive.line,
+                                0,
+                                format!("can't read included `{}`: {}", included.display(), e),
+                            ));
+                        }
+                    }
+                }
+            }
+
+            self.push(path, part, enclosing);
+        }
+
+        chain.pop();
+        Ok(())
+    }
+
+    fn push(&mut self, path: &Path, stanza: Stanza, enclosing: &[Directive]) {
+        self.stanzas.push(SourceStanza {
+            path: path.to_path_buf(),
+            stanza,
+            enclosing: enclosing.to_vec(),
+        });
+    }
+
+    /// Resolves effective options for `name` as given on ssh command line.
+    pub fn resolve(&self, name: &str) -> EffectiveConfig {
+        let local_user = rule::local_user();
+        let mut config = EffectiveConfig {
+            host: name.to_string(),
+            diagnostics: self.diagnostics.clone(),
+            ..Default::default()
+        };
+
+        for source in &self.stanzas {
+            let headers = source.enclosing.iter().chain(source.stanza.header.iter());
+
+            let mut active = true;
+            for header in headers {
+                match evaluate(header, name, &config, &local_user) {
+                    Applicability::Applies => {}
+                    Applicability::DoesNotApply => active = false,
+                    Applicability::Unknown => {
+                        config.undetermined.push(Origin::File {
+                            path: source.path.clone(),
+                            line: header.line,
+                            stanza: header.to_string(),
+                        });
+                        active = false;
+                    }
+                }
+                if !active {
+                    break;
+                }
+            }
+            if !active {
+                continue;
+            }
+
+            let stanza = match &source.stanza.header {
+                Some(header) => header.to_string(),
+                None => String::from("global"),
+            };
+            for directive in &source.stanza.directives {
+                config.set(
+                    directive,
+                    Origin::File {
+                        path: source.path.clone(),
+                        line: directive.line,
+                        stanza: stanza.clone(),
+                    },
+                );
+            }
+        }
+
+        config.set_default("hostname", name.to_string());
+        config.set_default("user", local_user);
+        config.set_default("port", String::from("22"));
+
+        config
+    }
+}
+
+fn evaluate(
+    header: &Directive,
+    name: &str,
+    config: &EffectiveConfig,
+    local_user: &str,
+) -> Applicability {
+    match MatchRule::from_header(header) {
+        // `Match` criteria see values set by stanzas above them
+        Some(rule) => {
+            let host = Host {
+                name: name.to_string(),
+                host_name: config.value("hostname"),
+                user: config.value("user"),
+                ..Default::default()
+            };
+            rule.applies_to_name(name, &host, local_user)
+        }
+        None => {
+            let patterns: Vec<_> = header
+                .args
+                .iter()
+                .map(|p| pattern::HostPattern::new(p))
+                .collect();
+            if pattern::matches_list(&patterns, name) {
+                Applicability::Applies
+            } else {
+                Applicability::DoesNotApply
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_first_match_wins() {
+        let dir = TempDir::new("resolve").unwrap();
+        let config = dir.path().join("config");
+        fs::write(
+            &config,
+            "Host web1 web2\n    User deploy\n    IdentityFile ~/.ssh/web\n\nHost *\n    User nobody\n    IdentityFile ~/.ssh/id_rsa\n    Port 2222\n",
+        )
+        .unwrap();
+
+        let resolver = Resolver::from_paths([config.clone()]).unwrap();
+        let effective = resolver.resolve("web2");
+
+        assert_eq!(effective.value("user").as_deref(), Some("deploy"));
+        assert_eq!(effective.value("port").as_deref(), Some("2222"));
+        assert_eq!(effective.value("hostname").as_deref(), Some("web2"));
+        assert_eq!(effective.get_all("identityfile").count(), 2);
+
+        let identity = &effective.paths("identityfile")[0];
+        assert_eq!(identity.raw, "~/.ssh/web");
+        assert_eq!(
+            identity.expanded,
+            dirs::home_dir().unwrap().join(".ssh/web")
+        );
+        assert_eq!(
+            effective.get("port").unwrap().origin,
+            Origin::File {
+                path: config,
+                line: 8,
+                stanza: String::from("Host *"),
+            }
+        );
+        assert_eq!(effective.get("hostname").unwrap().origin, Origin::Default);
+    }
+
+    #[test]
+    fn test_include_expanded_in_place() {
+        let dir = TempDir::new("resolve").unwrap();
+        let config = dir.path().join("config");
+        let included = dir.path().join("included");
+        fs::write(
+            &config,
+            format!(
+                "Host db\n    Include {}\n    User late\nHost *\n    User fallback\n",
+                included.display()
+            ),
+        )
+        .unwrap();
+        fs::write(&included, "Host *\n    User included\n    Port 2200\n").unwrap();
+
+        let resolver = Resolver::from_paths([config]).unwrap();
+
+        let db = resolver.resolve("db");
+        assert_eq!(db.value("user").as_deref(), Some("included"));
+        assert_eq!(
+            db.get("user").unwrap().origin,
+            Origin::File {
+                path: included,
+                line: 2,
+                stanza: String::from("Host *"),
+            }
+        );
+
+        // Include inside `Host db` doesn't apply to other hosts
+        let other = resolver.resolve("other");
+        assert_eq!(other.value("user").as_deref(), Some("fallback"));
+        assert_eq!(other.value("port").as_deref(), Some("22"));
+    }
+
+    #[test]
+    fn test_match_sees_earlier_values() {
+        let dir = TempDir::new("resolve").unwrap();
+        let config = dir.path().join("config");
+        fs::write(
+            &config,
+            "Host app\n    HostName app.prod\nMatch host *.prod\n    ProxyJump bastion\nMatch exec \"true\"\n    Port 1\n",
+        )
+        .unwrap();
+
+        let effective = Resolver::from_paths([config]).unwrap().resolve("app");
+
+        assert_eq!(effective.value("proxyjump").as_deref(), Some("bastion"));
+        assert_eq!(effective.value("port").as_deref(), Some("22"));
+        assert_eq!(effective.undetermined.len(), 1);
+    }
+
+    #[test]
+    fn test_unreadable_include_is_reported() {
+        let dir = TempDir::new("resolve").unwrap();
+        let config = dir.path().join("config");
+        let included = dir.path().join("included");
+        fs::write(
+            &config,
+            format!(
+                "Host app\n    Include {}\n    User deploy\n",
+                included.display()
+            ),
+        )
+        .unwrap();
+        fs::write(&included, [0xff, 0xfe]).unwrap();
+
+        let resolver = Resolver::from_paths([config.clone()]).unwrap();
+        let effective = resolver.resolve("app");
+
+        // Invalid UTF-8 file is skipped, the rest of the stanza still applies
+        assert_eq!(effective.value("user").as_deref(), Some("deploy"));
+        assert_eq!(effective.diagnostics.len(), 1);
+        assert_eq!(effective.diagnostics[0].path, config);
+        assert_eq!(effective.diagnostics[0].line, 2);
+    }
+
+    #[test]
+    fn test_include_depth_is_the_same_as_on_ingest() {
+        let dir = TempDir::new("resolve").unwrap();
+        let files: Vec<PathBuf> = (0..20).map(|i| dir.path().join(i.to_string())).collect();
+        for (file, next) in files.iter().zip(&files[1..]) {
+            fs::write(file, format!("Include {}\n", next.display())).unwrap();
+        }
+        fs::write(&files[19], "Host last\n").unwrap();
+
+        let resolver = Resolver::from_paths([files[0].clone()]).unwrap();
+        let read: HashSet<&Path> = resolver
+            .stanzas()
+            .iter()
+            .map(|s| s.path.as_path())
+            .collect();
+
+        let mut diagnostics = vec![];
+        let collected = IncludeResolver::default()
+            .collect(files[0].clone(), None, &mut diagnostics)
+            .unwrap();
+
+        assert_eq!(read.len(), collected.len());
+        assert_eq!(resolver.diagnostics().len(), 1);
+        assert_eq!(resolver.diagnostics()[0], diagnostics[0]);
+    }
+}
@@ -0,0 +1,342 @@
+use serde::{Deserialize, Serialize};
+use surrealdb::{sql::Thing, Connection, Error, Surreal};
+
+use crate::{
+    host::{
+        pattern::{self, HostPattern},
+        source::HostSource,
+        Host,
+    },
+    tokenizer::{Directive, Stanza, StanzaKind},
+};
+
+/// Criterion keywords which can be evaluated from the config alone.
+const STATIC_CRITERIA: [&str; 5] = ["all", "host", "originalhost", "user", "localuser"];
+
+/// Criterion keywords which don't take an argument.
+const SINGLE_CRITERIA: [&str; 3] = ["all", "canonical", "final"];
+
+/// Single criterion of a `Match` line, e.g. `host=*.prod` or `!exec "test -f /tmp/x"`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MatchCriterion {
+    /// Lowercase criterion keyword.
+    pub keyword: String,
+    pub argument: Option<String>,
+    pub negated: bool,
+}
+
+impl MatchCriterion {
+    /// Criterion can be evaluated without running ssh, e.g. `exec` or `localnetwork` can't.
+    pub fn is_static(&self) -> bool {
+        STATIC_CRITERIA.contains(&self.keyword.as_str())
+    }
+
+    fn evaluate(&self, name: &str, host: &Host, local_user: &str) -> Applicability {
+        let argument = self.argument.as_deref().unwrap_or_default();
+        let result = match self.keyword.as_str() {
+            "all" => true,
+            "host" => matches_csv(argument, host.host_name.as_deref().unwrap_or(name)),
+            "originalhost" => matches_csv(argument, name),
+            "user" => matches_csv(argument, host.user.as_deref().unwrap_or(local_user)),
+            "localuser" => matches_csv(argument, local_user),
+            _ => return Applicability::Unknown,
+        };
+
+        if result != self.negated {
+            Applicability::Applies
+        } else {
+            Applicability::DoesNotApply
+        }
+    }
+}
+
+/// Whether a `Match` block applies to a host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Applicability {
+    Applies,
+    DoesNotApply,
+    /// Outcome depends on criteria which can't be evaluated statically.
+    Unknown,
+}
+
+/// Single option inside `Match` block.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RuleOption {
+    /// Lowercase option keyword.
+    pub keyword: String,
+    pub args: Vec<String>,
+}
+
+/// Conditional rule created from ssh config `Match` block.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct MatchRule {
+    /// Criteria as written after `Match` keyword.
+    pub condition: String,
+    pub criteria: Vec<MatchCriterion>,
+    /// Options in order of appearance.
+    pub options: Vec<RuleOption>,
+    /// Rule contains criteria which can't be evaluated statically, such as `exec`.
+    pub dynamic: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EnhancedRule {
+    pub rule: MatchRule,
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub source: Option<HostSource>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RuleRecord {
+    pub id: Thing,
+    pub rule: MatchRule,
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub source: Option<HostSource>,
+}
+
+impl MatchRule {
+    /// Creates rule from `Match` stanza, returns `None` for other stanzas.
+    pub fn from_stanza(stanza: &Stanza) -> Option<Self> {
+        if stanza.kind != StanzaKind::Match {
+            return None;
+        }
+
+        let mut rule = Self::from_header(stanza.header.as_ref()?)?;
+        rule.options = stanza
+            .directives
+            .iter()
+            .map(|directive| RuleOption {
+                keyword: directive.keyword.to_lowercase(),
+                args: directive.args.clone(),
+            })
+            .collect();
+
+        Some(rule)
+    }
+
+    /// Creates rule without options from `Match` line, returns `None` for other lines.
+    pub fn from_header(header: &Directive) -> Option<Self> {
+        if !header.is("match") {
+            return None;
+        }
+
+        let criteria = parse_criteria(&header.args);
+
+        Some(Self {
+            condition: header.to_string()[header.keyword.len()..]
+                .trim_start()
+                .to_string(),
+            dynamic: criteria.iter().any(|c| !c.is_static()),
+            criteria,
+            options: vec![],
+        })
+    }
+
+    /// Statically evaluates rule for `host`, it applies when it applies to any of host's aliases.
+    pub fn applies_to(&self, host: &Host, local_user: &str) -> Applicability {
+        let mut result = Applicability::DoesNotApply;
+
+        for alias in host.aliases.iter().chain(std::iter::once(&host.name)) {
+            match self.applies_to_name(alias, host, local_user) {
+                Applicability::Applies => return Applicability::Applies,
+                Applicability::Unknown => result = Applicability::Unknown,
+                Applicability::DoesNotApply => {}
+            }
+        }
+
+        result
+    }
+
+    /// Statically evaluates rule for `name` given on command line, all criteria have to match.
+    pub fn applies_to_name(&self, name: &str, host: &Host, local_user: &str) -> Applicability {
+        let mut result = Applicability::Applies;
+
+        for criterion in &self.criteria {
+            match criterion.evaluate(name, host, local_user) {
+                Applicability::DoesNotApply => return Applicability::DoesNotApply,
+                Applicability::Unknown => result = Applicability::Unknown,
+                Applicability::Applies => {}
+            }
+        }
+
+        result
+    }
+}
+
+impl EnhancedRule {
+    /// Stores rule, the same `Match` line in another file or at another place is
+    /// a rule of its own.
+    pub async fn create_or_update<C: Connection>(
+        db: &Surreal<C>,
+        data: EnhancedRule,
+    ) -> surrealdb::Result<Thing> {
+        let condition = data.rule.condition.clone();
+        let source = data.source.clone();
+
+        if let Some(record) = Self::find(db, &condition, source.as_ref()).await? {
+            let _: Option<EnhancedRule> = db
+                .update(("rule", &record.id.id.to_string()))
+                .content(data)
+                .await?;
+            return Ok(record.id);
+        }
+
+        let _: Option<EnhancedRule> = db.create("rule").content(data).await?;
+
+        Self::find(db, &condition, source.as_ref())
+            .await?
+            .map(|r| r.id)
+            .ok_or(Error::Db(surrealdb::error::Db::InvalidModel {
+                message: String::from("Failed to create new rule"),
+            }))
+    }
+
+    /// Rule with `condition` whose stanza starts at the same line of the same file.
+    async fn find<C: Connection>(
+        db: &Surreal<C>,
+        condition: &str,
+        source: Option<&HostSource>,
+    ) -> surrealdb::Result<Option<RuleRecord>> {
+        db.query(
+            "SELECT * FROM rule WHERE rule.condition = $condition \
+            AND source.path = $path AND source.start = $start LIMIT 1",
+        )
+        .bind(("condition", condition.to_string()))
+        .bind(("path", source.map(|s| s.path.clone())))
+        .bind(("start", source.map(|s| s.start)))
+        .await?
+        .take(0)
+    }
+
+    pub async fn get_all<C: Connection>(db: &Surreal<C>) -> surrealdb::Result<Vec<RuleRecord>> {
+        db.select("rule").await
+    }
+
+    /// Returns rules which apply, or may apply, to `host`.
+    pub async fn applying_to<C: Connection>(
+        db: &Surreal<C>,
+        host: &Host,
+    ) -> surrealdb::Result<Vec<(RuleRecord, Applicability)>> {
+        let local_user = local_user();
+
+        Ok(Self::get_all(db)
+            .await?
+            .into_iter()
+            .map(|record| {
+                let applicability = record.rule.applies_to(host, &local_user);
+                (record, applicability)
+            })
+            .filter(|(_, a)| *a != Applicability::DoesNotApply)
+            .collect())
+    }
+}
+
+/// Name of the user running sshed, used for `localuser` and as default for `user`.
+pub fn local_user() -> String {
+    config::expand::local_user().unwrap_or_default()
+}
+
+fn parse_criteria(tokens: &[String]) -> Vec<MatchCriterion> {
+    let mut tokens = tokens.iter().cloned();
+    let mut criteria = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        let (keyword, mut argument) = match token.split_once('=') {
+            Some((k, v)) => (k.to_string(), Some(v.to_string())),
+            None => (token, None),
+        };
+        let (keyword, negated) = match keyword.strip_prefix('!') {
+            Some(k) => (k.to_lowercase(), true),
+            None => (keyword.to_lowercase(), false),
+        };
+
+        if argument.is_none() && !SINGLE_CRITERIA.contains(&keyword.as_str()) {
+            argument = tokens.next();
+        }
+
+        criteria.push(MatchCriterion {
+            keyword,
+            argument,
+            negated,
+        });
+    }
+
+    criteria
+}
+
+fn matches_csv(list: &str, name: &str) -> bool {
+    let patterns: Vec<HostPattern> = list.split(',').map(HostPattern::new).collect();
+    pattern::matches_list(&patterns, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::tokenize;
+
+    fn rule(block: &str) -> MatchRule {
+        MatchRule::from_stanza(&tokenize(block)[0]).unwrap()
+    }
+
+    #[test]
+    fn test_parse_match_block() {
+        let rule = rule("Match host=*.prod user deploy\n    ProxyJump bastion\n    User=ops");
+
+        assert_eq!(rule.condition, "host=*.prod user deploy");
+        assert_eq!(rule.criteria.len(), 2);
+        assert_eq!(rule.criteria[1].keyword, "user");
+        assert_eq!(rule.criteria[1].argument.as_deref(), Some("deploy"));
+        assert_eq!(rule.options[1].keyword, "user");
+        assert_eq!(rule.options[1].args, vec!["ops".to_string()]);
+        assert!(!rule.dynamic);
+        assert!(MatchRule::from_stanza(&tokenize("Host foo")[0]).is_none());
+    }
+
+    #[test]
+    fn test_exec_is_dynamic() {
+        let rule = rule("Match !exec \"test -f /tmp/vpn\" host *.corp");
+
+        assert!(rule.dynamic);
+        assert!(rule.criteria[0].negated);
+        assert_eq!(
+            rule.criteria[0].argument.as_deref(),
+            Some("test -f /tmp/vpn")
+        );
+        assert!(rule.criteria[1].is_static());
+    }
+
+    #[test]
+    fn test_applies_to() {
+        let host = Host {
+            name: "db1".to_string(),
+            aliases: vec!["db1".to_string(), "db1.prod".to_string()],
+            user: Some("deploy".to_string()),
+            ..Default::default()
+        };
+
+        let applicability = |block| rule(block).applies_to(&host, "me");
+        assert_eq!(
+            applicability("Match originalhost *.prod user deploy"),
+            Applicability::Applies
+        );
+        assert_eq!(
+            applicability("Match host *.dev"),
+            Applicability::DoesNotApply
+        );
+        assert_eq!(
+            applicability("Match !localuser root"),
+            Applicability::Applies
+        );
+        assert_eq!(applicability("Match all"), Applicability::Applies);
+        assert_eq!(
+            applicability("Match host db1 exec true"),
+            Applicability::Unknown
+        );
+        assert_eq!(
+            applicability("Match host db2 exec true"),
+            Applicability::DoesNotApply
+        );
+    }
+}
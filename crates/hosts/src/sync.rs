@@ -0,0 +1,351 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+};
+
+use surrealdb::{sql::Thing, Connection, Surreal};
+
+use crate::{
+    cst,
+    host::{source::HostSource, EnhancedHost, Host, HostRecord, WildcardHost},
+    rule::{EnhancedRule, MatchRule},
+    tokenizer::{self, Stanza},
+    IngestReport,
+};
+
+/// How a host changed since the previous ingest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Changed,
+    Unchanged,
+    Removed,
+}
+
+/// Changed hosts of a single ssh config file.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FileDiff {
+    /// Empty for hosts stored before their source was recorded.
+    pub path: PathBuf,
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl FileDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Differences between ssh config and database found while ingesting, per file.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SyncReport {
+    pub files: Vec<FileDiff>,
+    /// Names of hosts read during ingest, changed or not.
+    ingested: HashSet<String>,
+    /// Wildcard stanzas and `Match` rules stored during ingest.
+    stored: HashSet<Thing>,
+}
+
+impl SyncReport {
+    pub fn is_empty(&self) -> bool {
+        self.files.iter().all(FileDiff::is_empty)
+    }
+
+    pub fn record(&mut self, path: PathBuf, name: String, change: Change) {
+        if change != Change::Removed {
+            self.ingested.insert(name.clone());
+        }
+
+        let diff = match self.files.iter().position(|f| f.path == path) {
+            Some(index) => &mut self.files[index],
+            None => {
+                self.files.push(FileDiff {
+                    path,
+                    ..Default::default()
+                });
+                self.files.last_mut().unwrap()
+            }
+        };
+
+        match change {
+            Change::Added => diff.added.push(name),
+            Change::Changed => diff.changed.push(name),
+            Change::Removed => diff.removed.push(name),
+            Change::Unchanged => {}
+        }
+    }
+
+    /// Marks wildcard or rule record as read during ingest, so it isn't stale.
+    pub fn keep(&mut self, id: Thing) {
+        self.stored.insert(id);
+    }
+
+    pub fn extend(&mut self, other: SyncReport) {
+        self.ingested.extend(other.ingested);
+        self.stored.extend(other.stored);
+        for diff in other.files {
+            for name in diff.added {
+                self.record(diff.path.clone(), name, Change::Added);
+            }
+            for name in diff.changed {
+                self.record(diff.path.clone(), name, Change::Changed);
+            }
+            for name in diff.removed {
+                self.record(diff.path.clone(), name, Change::Removed);
+            }
+        }
+    }
+}
+
+impl Display for SyncReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for diff in self.files.iter().filter(|d| !d.is_empty()) {
+            if diff.path.as_os_str().is_empty() {
+                writeln!(f, "(unknown file)")?;
+            } else {
+                writeln!(f, "{}", diff.path.display())?;
+            }
+            for name in &diff.added {
+                writeln!(f, "  + {}", name)?;
+            }
+            for name in &diff.changed {
+                writeln!(f, "  ~ {}", name)?;
+            }
+            for name in &diff.removed {
+                writeln!(f, "  - {}", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Deletes hosts which are no longer defined in ssh config, together with their relations.
+///
+/// A stored host is stale when it wasn't read during ingest and it either comes from
+/// one of the ingested files, its file doesn't define it anymore, or its source is unknown.
+/// Wildcard stanzas and `Match` rules are removed the same way. Removed hosts are
+/// added to `report`.
+pub async fn remove_stale<C: Connection>(db: &Surreal<C>, report: &mut IngestReport) -> Result<()> {
+    let records: Vec<HostRecord> = db.select("host").await.map_err(to_io)?;
+    let mut files = SourceFiles::default();
+
+    for record in records {
+        if report.changes.ingested.contains(&record.host.name) {
+            continue;
+        }
+
+        let stale = match &record.source {
+            Some(source) => {
+                report.files.contains(&source.path) || !files.defines(source, &record.host)
+            }
+            None => true,
+        };
+        if !stale {
+            continue;
+        }
+
+        EnhancedHost::delete(db, &record.id).await.map_err(to_io)?;
+
+        let path = record.source.map(|s| s.path).unwrap_or_default();
+        report
+            .changes
+            .record(path, record.host.name, Change::Removed);
+    }
+
+    for record in WildcardHost::get_all(db).await.map_err(to_io)? {
+        let stale = match &record.source {
+            Some(source) => {
+                report.files.contains(&source.path) || !files.defines(source, &record.host)
+            }
+            None => true,
+        };
+        if stale && !report.changes.stored.contains(&record.id) {
+            delete(db, &record.id).await?;
+        }
+    }
+
+    for record in EnhancedRule::get_all(db).await.map_err(to_io)? {
+        let stale = match &record.source {
+            Some(source) => {
+                report.files.contains(&source.path) || !files.defines_rule(source, &record.rule)
+            }
+            None => true,
+        };
+        if stale && !report.changes.stored.contains(&record.id) {
+            delete(db, &record.id).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Files stored records come from, each one is read and tokenized once per sync.
+#[derive(Debug, Default)]
+struct SourceFiles {
+    stanzas: HashMap<PathBuf, Vec<Stanza>>,
+}
+
+impl SourceFiles {
+    /// Stanzas of file at `path`, none when it can't be read.
+    fn stanzas(&mut self, path: &Path) -> &[Stanza] {
+        self.stanzas.entry(path.to_path_buf()).or_insert_with(|| {
+            fs::read_to_string(path)
+                .map(|content| tokenizer::tokenize(&content))
+                .unwrap_or_default()
+        })
+    }
+
+    /// File of `source` still contains definition of `host`.
+    fn defines(&mut self, source: &HostSource, host: &Host) -> bool {
+        cst::find_host_stanza(self.stanzas(&source.path), host).is_some()
+    }
+
+    /// File of `source` still has the `Match` stanza of `rule`.
+    fn defines_rule(&mut self, source: &HostSource, rule: &MatchRule) -> bool {
+        self.stanzas(&source.path)
+            .iter()
+            .filter_map(MatchRule::from_stanza)
+            .any(|stored| stored.condition == rule.condition)
+    }
+}
+
+async fn delete<C: Connection>(db: &Surreal<C>, id: &Thing) -> Result<()> {
+    db.query("DELETE $record")
+        .bind(("record", id.clone()))
+        .await
+        .and_then(|response| response.check())
+        .map_err(to_io)?;
+
+    Ok(())
+}
+
+fn to_io(e: surrealdb::Error) -> Error {
+    Error::new(ErrorKind::Other, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Hosts;
+    use db::memory_db;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_report_groups_changes_by_file() {
+        let mut report = SyncReport::default();
+        report.record(PathBuf::from("a"), String::from("web"), Change::Added);
+        report.record(PathBuf::from("b"), String::from("db"), Change::Unchanged);
+
+        let mut other = SyncReport::default();
+        other.record(PathBuf::from("a"), String::from("old"), Change::Removed);
+        other.record(PathBuf::from("b"), String::from("cache"), Change::Changed);
+        report.extend(other);
+
+        assert_eq!(report.files.len(), 2);
+        assert_eq!(report.files[0].added, ["web"]);
+        assert_eq!(report.files[0].removed, ["old"]);
+        assert!(report.ingested.contains("db"));
+        assert!(!report.ingested.contains("old"));
+        assert_eq!(report.to_string(), "a\n  + web\n  - old\nb\n  ~ cache\n");
+    }
+
+    #[test]
+    fn test_source_files_are_read_once() {
+        let dir = TempDir::new("sync").unwrap();
+        let config = dir.path().join("config");
+        fs::write(&config, "Host web db\n    HostName 10.0.0.1\n").unwrap();
+        let stanzas = tokenizer::tokenize(&fs::read_to_string(&config).unwrap());
+        let source = HostSource::new(&config, &stanzas[0], "Host web db\n");
+        let host = |name: &str| Host {
+            name: name.to_string(),
+            aliases: vec![name.to_string()],
+            ..Default::default()
+        };
+
+        let mut files = SourceFiles::default();
+        assert!(files.defines(&source, &host("web")));
+
+        // Later lookups don't read the file again
+        fs::remove_file(&config).unwrap();
+        assert!(files.defines(&source, &host("db")));
+        assert!(!files.defines(&source, &host("cache")));
+    }
+
+    #[tokio::test]
+    async fn test_sync_removes_deleted_hosts() {
+        let dir = TempDir::new("sync").unwrap();
+        let db = memory_db().await.unwrap();
+
+        let config = dir.path().join("config");
+        fs::write(
+            &config,
+            "Host web\n    HostName 10.0.0.1\n\n#--[abc]\nHost old\n    HostName 10.0.0.2\n",
+        )
+        .unwrap();
+        let mut report = Hosts::parse_config(&db, config.clone(), None, None)
+            .await
+            .unwrap();
+        Hosts::sync(&db, &mut report).await.unwrap();
+        assert_eq!(report.changes.files[0].added, ["web", "old"]);
+
+        fs::write(&config, "Host web\n    HostName 10.0.0.3\n").unwrap();
+        let mut report = Hosts::parse_config(&db, config.clone(), None, None)
+            .await
+            .unwrap();
+        Hosts::sync(&db, &mut report).await.unwrap();
+
+        let diff = &report.changes.files[0];
+        assert_eq!(diff.changed, ["web"]);
+        assert_eq!(diff.removed, ["old"]);
+        assert!(EnhancedHost::get_host_by_name(&db, "old".to_string())
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_sync_removes_deleted_wildcards_and_rules() {
+        let dir = TempDir::new("sync").unwrap();
+        let db = memory_db().await.unwrap();
+
+        let config = dir.path().join("config");
+        fs::write(
+            &config,
+            "Host web\n    HostName 10.0.0.1\n\nMatch host 10.0.0.*\n    User deploy\n\nHost *\n    User nobody\n",
+        )
+        .unwrap();
+        let mut report = Hosts::parse_config(&db, config.clone(), None, None)
+            .await
+            .unwrap();
+        Hosts::sync(&db, &mut report).await.unwrap();
+        assert_eq!(WildcardHost::get_all(&db).await.unwrap().len(), 1);
+        assert_eq!(EnhancedRule::get_all(&db).await.unwrap().len(), 1);
+
+        // Unchanged file keeps both
+        let mut report = Hosts::parse_config(&db, config.clone(), None, None)
+            .await
+            .unwrap();
+        Hosts::sync(&db, &mut report).await.unwrap();
+        assert_eq!(WildcardHost::get_all(&db).await.unwrap().len(), 1);
+        assert_eq!(EnhancedRule::get_all(&db).await.unwrap().len(), 1);
+
+        fs::write(&config, "Host web\n    HostName 10.0.0.1\n").unwrap();
+        let mut report = Hosts::parse_config(&db, config.clone(), None, None)
+            .await
+            .unwrap();
+        Hosts::sync(&db, &mut report).await.unwrap();
+
+        assert!(WildcardHost::get_all(&db).await.unwrap().is_empty());
+        assert!(EnhancedRule::get_all(&db).await.unwrap().is_empty());
+        assert!(EnhancedHost::get_host_by_name(&db, "web".to_string())
+            .await
+            .unwrap()
+            .is_some());
+    }
+}
@@ -0,0 +1,336 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+/// Kind of ssh config stanza.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StanzaKind {
+    /// Options before the first `Host` or `Match` line, they apply to every host.
+    Global,
+    Host,
+    Match,
+}
+
+/// Single `Keyword args...` line of ssh config.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Directive {
+    /// Keyword as written in file.
+    pub keyword: String,
+    /// Arguments with quotes removed.
+    pub args: Vec<String>,
+    /// 1-based line number.
+    pub line: usize,
+    /// Comment lines directly above directive followed by its trailing comment, without `#`.
+    pub comment: Option<String>,
+}
+
+impl Directive {
+    pub fn is(&self, keyword: &str) -> bool {
+        self.keyword.eq_ignore_ascii_case(keyword)
+    }
+}
+
+impl Display for Directive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.keyword)?;
+        for arg in &self.args {
+            if arg.is_empty() || arg.contains(char::is_whitespace) {
+                write!(f, " \"{}\"", arg)?;
+            } else {
+                write!(f, " {}", arg)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `Host` or `Match` block together with metadata comments placed directly above it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stanza {
+    pub kind: StanzaKind,
+    /// `Host` or `Match` line, `None` for global stanza.
+    pub header: Option<Directive>,
+    /// Comment lines directly above header, e.g. `#--(groups)`, `#--[tags]` and `# comment`.
+    pub metadata: Vec<String>,
+    pub directives: Vec<Directive>,
+    /// First line of stanza including metadata, 1-based.
+    pub start: usize,
+    /// Last non-empty line of stanza, 1-based.
+    pub end: usize,
+}
+
+impl Stanza {
+    fn new(header: Option<Directive>, metadata: Vec<String>, start: usize) -> Self {
+        let kind = match &header {
+            Some(h) if h.is("match") => StanzaKind::Match,
+            Some(_) => StanzaKind::Host,
+            None => StanzaKind::Global,
+        };
+        let end = header.as_ref().map(|h| h.line).unwrap_or(start);
+
+        Self {
+            kind,
+            header,
+            metadata,
+            directives: vec![],
+            start,
+            end,
+        }
+    }
+
+    /// Arguments of `Host` or `Match` line.
+    pub fn patterns(&self) -> &[String] {
+        match &self.header {
+            Some(header) => &header.args,
+            None => &[],
+        }
+    }
+
+    /// Comments of options keyed by lowercase keyword, comments of repeated
+    /// options are joined.
+    pub fn option_comments(&self) -> BTreeMap<String, String> {
+        let mut comments: BTreeMap<String, String> = BTreeMap::new();
+
+        for directive in &self.directives {
+            if let Some(comment) = &directive.comment {
+                comments
+                    .entry(directive.keyword.to_lowercase())
+                    .and_modify(|c| {
+                        c.push('\n');
+                        c.push_str(comment);
+                    })
+                    .or_insert_with(|| comment.clone());
+            }
+        }
+
+        comments
+    }
+}
+
+/// Renders stanza back as normalized ssh config, without metadata.
+impl Display for Stanza {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(header) = &self.header {
+            writeln!(f, "{}", header)?;
+        }
+        for directive in &self.directives {
+            writeln!(f, "    {}", directive)?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits ssh config into stanzas.
+///
+/// Stanza boundaries are `Host` and `Match` lines, blank lines have no meaning.
+/// Both `Keyword value` and `Keyword=value` forms are accepted, double quoted
+/// arguments may contain whitespace and CRLF line endings are handled.
+pub fn tokenize(content: &str) -> Vec<Stanza> {
+    let mut stanzas: Vec<Stanza> = vec![];
+    let mut current: Option<Stanza> = None;
+    let mut comments: Vec<(usize, String)> = vec![];
+
+    for (index, raw) in content.lines().enumerate() {
+        let number = index + 1;
+        let line = raw.trim();
+
+        if line.is_empty() {
+            comments.clear();
+            continue;
+        }
+        if line.starts_with('#') {
+            comments.push((number, line.to_string()));
+            continue;
+        }
+
+        let Some(mut directive) = parse_line(line, number) else {
+            continue;
+        };
+
+        if directive.is("host") || directive.is("match") {
+            if let Some(stanza) = current.take() {
+                stanzas.push(stanza);
+            }
+            let start = comments.first().map(|(n, _)| *n).unwrap_or(number);
+            let metadata = comments.drain(..).map(|(_, c)| c).collect();
+            current = Some(Stanza::new(Some(directive), metadata, start));
+            continue;
+        }
+
+        // Comments directly above an option belong to it
+        let mut lines: Vec<&str> = comments.iter().map(|(_, c)| comment_text(c)).collect();
+        lines.extend(directive.comment.as_deref());
+        directive.comment = (!lines.is_empty()).then(|| lines.join("\n"));
+        comments.clear();
+
+        let stanza = current.get_or_insert_with(|| Stanza::new(None, vec![], number));
+        stanza.end = number;
+        stanza.directives.push(directive);
+    }
+
+    if let Some(stanza) = current {
+        stanzas.push(stanza);
+    }
+
+    stanzas
+}
+
+/// Parses single line into directive. Returns `None` for blank lines and comments.
+pub fn parse_line(line: &str, number: usize) -> Option<Directive> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (line, comment) = split_comment(line);
+    let line = line.trim_end();
+
+    let keyword_end = line
+        .find(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or(line.len());
+    let rest = line[keyword_end..].trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest);
+
+    Some(Directive {
+        keyword: line[..keyword_end].to_string(),
+        args: split_args(rest),
+        line: number,
+        comment: (!comment.is_empty()).then(|| comment_text(comment).to_string()),
+    })
+}
+
+/// Text of `# comment` line, without `#` and the space after it.
+pub fn comment_text(line: &str) -> &str {
+    let text = line.trim().strip_prefix('#').unwrap_or(line);
+    text.strip_prefix(' ').unwrap_or(text)
+}
+
+/// Splits trailing ` # comment` off line, `#` inside quotes doesn't start a comment.
+pub fn split_comment(line: &str) -> (&str, &str) {
+    let mut quoted = false;
+    let mut after_space = false;
+
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted && after_space => return (&line[..index], &line[index..]),
+            _ => {}
+        }
+        after_space = c.is_whitespace();
+    }
+
+    (line, "")
+}
+
+/// Splits arguments on whitespace, double quoted parts are kept together.
+pub fn split_args(input: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut in_arg = false;
+
+    for c in input.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_arg = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if in_arg {
+        args.push(current);
+    }
+
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stanzas_without_blank_lines() {
+        let stanzas = tokenize("Host a\n    User x\nHost b\n    User y\n");
+
+        assert_eq!(stanzas.len(), 2);
+        assert_eq!(stanzas[0].patterns(), ["a"]);
+        assert_eq!(stanzas[1].directives[0].args, ["y"]);
+        assert_eq!((stanzas[1].start, stanzas[1].end), (3, 4));
+    }
+
+    #[test]
+    fn test_blank_lines_inside_stanza() {
+        let stanzas = tokenize("Host a\n    User x\n\n    # note\n\n    Port 22\n");
+
+        assert_eq!(stanzas.len(), 1);
+        assert_eq!(stanzas[0].directives.len(), 2);
+        assert_eq!(stanzas[0].end, 6);
+        assert_eq!(stanzas[0].directives[0].comment, None);
+    }
+
+    #[test]
+    fn test_option_comments() {
+        let stanzas = tokenize(
+            "# Web\nHost a\n    # legacy appliance,\n    # needs CBC\n    Ciphers aes128-cbc # until 2026\n    IdentityFile a # work\n    IdentityFile b # home\n    User x\n",
+        );
+
+        assert_eq!(stanzas[0].metadata, ["# Web"]);
+        assert_eq!(
+            stanzas[0].directives[0].comment.as_deref(),
+            Some("legacy appliance,\nneeds CBC\nuntil 2026")
+        );
+        assert_eq!(
+            stanzas[0].option_comments(),
+            BTreeMap::from([
+                (
+                    String::from("ciphers"),
+                    String::from("legacy appliance,\nneeds CBC\nuntil 2026")
+                ),
+                (String::from("identityfile"), String::from("work\nhome")),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_metadata_attached_to_next_stanza() {
+        let content =
+            "Include config.d/*\r\n\r\nHost a\r\n    User x\r\n#--(servers)\r\n#--[abc]\r\n# Comment\r\nMatch host=*.prod\r\n    User deploy\r\n";
+        let stanzas = tokenize(content);
+
+        assert_eq!(stanzas.len(), 3);
+        assert_eq!(stanzas[0].kind, StanzaKind::Global);
+        assert!(stanzas[1].metadata.is_empty());
+        assert_eq!(stanzas[2].kind, StanzaKind::Match);
+        assert_eq!(
+            stanzas[2].metadata,
+            ["#--(servers)", "#--[abc]", "# Comment"]
+        );
+        assert_eq!(stanzas[2].start, 5);
+        assert_eq!(stanzas[2].patterns(), ["host=*.prod"]);
+    }
+
+    #[test]
+    fn test_key_value_and_quotes() {
+        let directive = parse_line("  IdentityFile=\"~/.ssh/my key\" other", 1).unwrap();
+        assert_eq!(directive.keyword, "IdentityFile");
+        assert_eq!(directive.args, ["~/.ssh/my key", "other"]);
+        assert_eq!(
+            directive.to_string(),
+            "IdentityFile \"~/.ssh/my key\" other"
+        );
+
+        let directive = parse_line("Port = 22", 1).unwrap();
+        assert_eq!(directive.args, ["22"]);
+
+        let directive = parse_line("User deploy # \"ops\" user", 1).unwrap();
+        assert_eq!(directive.args, ["deploy"]);
+        assert_eq!(parse_line("User a#b", 1).unwrap().args, ["a#b"]);
+    }
+}
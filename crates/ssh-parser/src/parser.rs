@@ -1,82 +1,218 @@
 use std::{
-    io::Error,
-    path::PathBuf,
-    str::FromStr,
-    sync::{Arc, Mutex, MutexGuard},
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
 };
 
-use config::AppConfig;
+pub mod watch;
+use config::{events::ConfigEvent, AppConfig, Source};
 use db::DbRuntime;
-use hosts::Hosts;
+use glob::{glob, Pattern};
+use hosts::{
+    glob_base, groupname_from_path,
+    include::IncludeResolver,
+    resolve::{EffectiveConfig, Resolver},
+    Hosts, IngestReport,
+};
 use surrealdb::{Connection, Surreal};
+use watch::WatchSet;
 
 pub struct SshParser {}
 
 impl SshParser {
-    pub fn init(db: DbRuntime, configuration: Arc<Mutex<AppConfig>>) -> Result<(), Error> {
+    pub fn init<C: Connection>(
+        db: &DbRuntime<C>,
+        configuration: Arc<Mutex<AppConfig>>,
+    ) -> Result<IngestReport, Error> {
         db.runtime
             .block_on(async { parse_ssh_config(&db.db, configuration).await })
     }
+
+    /// Keeps database in sync with ssh config files and files they include while
+    /// they are edited, blocks until the watcher fails.
+    ///
+    /// Only files whose content changed are re-ingested, `on_change` gets the outcome.
+    /// Configuration changes received on `events` make it scan ssh config again, and
+    /// reconnect the database when storage changed.
+    pub fn watch(
+        db: &mut DbRuntime,
+        configuration: Arc<Mutex<AppConfig>>,
+        events: mpsc::Receiver<ConfigEvent>,
+        on_change: impl FnMut(IngestReport),
+    ) -> Result<(), Error> {
+        watch::watch(db, configuration, events, on_change)
+    }
+
+    /// Computes options ssh would use to connect to `host`, together with their origin.
+    pub fn resolve(
+        configuration: Arc<Mutex<AppConfig>>,
+        host: &str,
+    ) -> Result<EffectiveConfig, Error> {
+        let (sources, excludes) = get_sources(&configuration);
+
+        let mut paths = vec![];
+        for source in &sources {
+            paths.extend(source_roots(source, &excludes)?.into_iter().map(|(p, _)| p));
+        }
+
+        Ok(Resolver::from_paths(paths)?.resolve(host))
+    }
 }
 
 async fn parse_ssh_config<C: Connection>(
     db: &Surreal<C>,
     configuration: Arc<Mutex<AppConfig>>,
-) -> Result<(), Error> {
-    let config = configuration.lock().unwrap();
-    let path = get_path(config);
+) -> Result<IngestReport, Error> {
+    let mut report = read_ssh_config(db, configuration).await?;
+    Hosts::sync(db, &mut report).await?;
+
+    Ok(report)
+}
 
-    if path.to_str().unwrap().ends_with('*') {
-        let paths = expand_path(path);
-        for path in paths {
-            Hosts::parse_config(&db, path.clone(), groupname_from_path(&path)).await?;
+/// Ingests every file of configured sources, without removing stale hosts.
+async fn read_ssh_config<C: Connection>(
+    db: &Surreal<C>,
+    configuration: Arc<Mutex<AppConfig>>,
+) -> Result<IngestReport, Error> {
+    let (sources, excludes) = get_sources(&configuration);
+
+    let mut report = IngestReport::default();
+    for source in &sources {
+        for (path, group) in source_roots(source, &excludes)? {
+            report.extend(Hosts::parse_config(db, path, group, Some(source)).await?);
         }
-        Ok(())
-    } else {
-        Hosts::parse_config(&db, path, None).await
     }
+
+    Ok(report)
 }
 
-fn groupname_from_path(path: &PathBuf) -> Option<String> {
-    path.to_str().unwrap().split("/").last().map(String::from)
+/// Files read by [`parse_ssh_config`] and patterns new files are picked up by.
+fn watch_set(configuration: Arc<Mutex<AppConfig>>) -> Result<WatchSet, Error> {
+    let (sources, excludes) = get_sources(&configuration);
+
+    let mut set = WatchSet::new(excludes.clone(), IncludeResolver::new());
+    for source in &sources {
+        let path = source.expanded_path();
+        if is_glob(&path) {
+            set.add_pattern(&path, Some(source));
+        }
+
+        let includes = IncludeResolver::new().with_origin(Some(source.clone()));
+        for (root, group) in source_roots(source, &excludes)? {
+            for file in includes.collect(root, group, &mut vec![])? {
+                set.add_file(&file);
+            }
+        }
+    }
+
+    Ok(set)
 }
 
-fn expand_path(path: PathBuf) -> Vec<PathBuf> {
-    let parent = path.parent().expect("Path must have a parent directory");
+/// Root files of `source` with groups derived from their location below the glob.
+fn source_roots(
+    source: &Source,
+    excludes: &[Pattern],
+) -> Result<Vec<(PathBuf, Option<String>)>, Error> {
+    let path = source.expanded_path();
+    if !is_glob(&path) {
+        return Ok(vec![(path, None)]);
+    }
+
+    let base = glob_base(&path);
+    Ok(expand_path(path, excludes)?
+        .into_iter()
+        .map(|p| {
+            let group = groupname_from_path(&p, &base);
+            (p, group)
+        })
+        .collect())
+}
+
+fn is_glob(path: &Path) -> bool {
+    path.to_str().is_some_and(|p| p.contains(['*', '?', '[']))
+}
+
+/// Expands glob pattern into matching files.
+///
+/// Supports `*`, `?`, `[...]` and `**`. Directories are skipped and files matching
+/// any of `excludes` are left out. Returned paths are sorted.
+fn expand_path(path: PathBuf, excludes: &[Pattern]) -> Result<Vec<PathBuf>, Error> {
     let pattern = path
-        .file_name()
-        .expect("Path must have a filename")
         .to_str()
-        .expect("Path must be valid UTF-8");
-    let mut paths = Vec::new();
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Path must be valid UTF-8"))?;
 
-    process_directory(parent, pattern, &mut paths);
-    paths
+    let mut paths: Vec<PathBuf> = glob(pattern)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?
+        .filter_map(|entry| entry.ok())
+        .filter(|path| path.is_file())
+        .filter(|path| !is_excluded(path, excludes))
+        .collect();
+
+    paths.sort();
+    paths.dedup();
+    Ok(paths)
 }
 
-fn process_directory(dir: &std::path::Path, pattern: &str, paths: &mut Vec<PathBuf>) {
-    if let Ok(entries) = std::fs::read_dir(dir) {
-        // !("Entries: {:?}", entries);
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                if path.is_dir() {
-                    process_directory(&path, pattern, paths);
-                } else {
-                    paths.push(entry.path());
-                }
-            }
-        }
-    }
+/// Patterns are matched against the whole path and against the file name alone,
+/// so `*.swp` excludes swap files in any directory.
+fn is_excluded(path: &Path, excludes: &[Pattern]) -> bool {
+    let file_name = path.file_name().and_then(|name| name.to_str());
+
+    excludes.iter().any(|pattern| {
+        pattern.matches_path(path) || file_name.is_some_and(|name| pattern.matches(name))
+    })
 }
 
-fn get_path(config: MutexGuard<'_, AppConfig>) -> PathBuf {
-    let path = match config.general.as_ref() {
-        Some(g) => match g.ssh_config_path.as_ref() {
-            Some(p) => PathBuf::from_str(p).expect("Invalid path string"),
-            None => panic!("SSH config path not found"),
-        },
-        None => panic!("SSH config path not found"),
+/// Enabled sources and exclude patterns of current configuration.
+fn get_sources(configuration: &Mutex<AppConfig>) -> (Vec<Source>, Vec<Pattern>) {
+    let config = configuration.lock().unwrap();
+    let Some(general) = config.general.as_ref() else {
+        return (vec![], vec![]);
     };
-    path
+
+    // Patterns were checked when configuration was read
+    let excludes = general
+        .exclude
+        .iter()
+        .flatten()
+        .filter_map(|p| Pattern::new(p).ok())
+        .collect();
+
+    (general.sources(), excludes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_expand_path_matches_only_pattern() {
+        let dir = TempDir::new("ssh").unwrap();
+        fs::create_dir(dir.path().join("config.d")).unwrap();
+        for file in [
+            "config",
+            "config.swp",
+            "known_hosts",
+            "id_rsa.pub",
+            "config.d/work",
+        ] {
+            fs::write(dir.path().join(file), "").unwrap();
+        }
+
+        let excludes = vec![Pattern::new("*.swp").unwrap()];
+        let paths = expand_path(dir.path().join("config*"), &excludes).unwrap();
+        assert_eq!(paths, vec![dir.path().join("config")]);
+
+        let paths = expand_path(dir.path().join("**/*"), &excludes).unwrap();
+        let names: Vec<_> = paths
+            .iter()
+            .map(|p| p.strip_prefix(dir.path()).unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["config", "config.d/work", "id_rsa.pub", "known_hosts"]
+        );
+    }
 }
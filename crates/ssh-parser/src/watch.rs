@@ -0,0 +1,516 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
+};
+
+use config::{
+    events::{ConfigChange, ConfigEvent},
+    AppConfig, Source,
+};
+use db::DbRuntime;
+use diagnostics::Diagnostic;
+use glob::Pattern;
+use hosts::{
+    glob_base, groupname_from_path,
+    host::source::{canonical_path, content_hash},
+    include::{parse_include, IncludeResolver, SourceFile},
+    Hosts, IngestReport,
+};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use surrealdb::{Connection, Surreal};
+
+/// Editors save in several steps (write temp file, rename, chmod), changes are
+/// processed once files were quiet for this long.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// ssh config files read during ingest and glob patterns new files may appear under.
+#[derive(Debug)]
+pub struct WatchSet {
+    /// Canonical path of every ingested file with its group and content hash.
+    files: HashMap<PathBuf, WatchedFile>,
+    /// Source and `Include` globs new files may appear under.
+    patterns: Vec<WatchedPattern>,
+    excludes: Vec<Pattern>,
+    includes: IncludeResolver,
+}
+
+#[derive(Debug, Clone)]
+struct WatchedFile {
+    group: Option<String>,
+    origin: Option<Source>,
+    hash: String,
+}
+
+#[derive(Debug)]
+struct WatchedPattern {
+    pattern: Pattern,
+    /// Directory groups of new files are derived from.
+    base: PathBuf,
+    origin: Option<Source>,
+}
+
+/// File whose content changed, `content` is `None` when it was deleted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangedFile {
+    pub path: PathBuf,
+    pub group: Option<String>,
+    pub origin: Option<Source>,
+    pub content: Option<String>,
+}
+
+impl WatchSet {
+    pub fn new(excludes: Vec<Pattern>, includes: IncludeResolver) -> Self {
+        Self {
+            files: HashMap::new(),
+            patterns: vec![],
+            excludes,
+            includes,
+        }
+    }
+
+    /// New files matching glob `pattern` are ingested too, as part of `origin`.
+    ///
+    /// Returns `false` when the pattern was already known or isn't a valid glob.
+    pub fn add_pattern(&mut self, pattern: &Path, origin: Option<&Source>) -> bool {
+        let Some(glob) = pattern.to_str().and_then(|p| Pattern::new(p).ok()) else {
+            return false;
+        };
+        if self.patterns.iter().any(|p| p.pattern == glob) {
+            return false;
+        }
+
+        self.patterns.push(WatchedPattern {
+            pattern: glob,
+            base: glob_base(pattern),
+            origin: origin.cloned(),
+        });
+        true
+    }
+
+    /// Remembers ingested file, together with `Include` patterns it contains.
+    ///
+    /// Returns `Include` arguments which weren't watched before.
+    pub fn add_file(&mut self, source: &SourceFile) -> Vec<String> {
+        let mut added = vec![];
+        for pattern in source.content.lines().filter_map(parse_include).flatten() {
+            let expanded = self.includes.expand(&pattern);
+            if self.add_pattern(&expanded, source.origin.as_ref()) {
+                added.push(pattern);
+            }
+        }
+
+        self.files.insert(
+            canonical_path(&source.path),
+            WatchedFile {
+                group: source.group.clone(),
+                origin: source.origin.clone(),
+                hash: content_hash(&source.content),
+            },
+        );
+
+        added
+    }
+
+    /// Directories containing watched files or new files may be created in.
+    pub fn directories(&self) -> Vec<PathBuf> {
+        let mut directories: Vec<PathBuf> = self
+            .files
+            .keys()
+            .filter_map(|path| path.parent().map(Path::to_path_buf))
+            .chain(self.patterns.iter().map(|p| p.base.clone()))
+            .filter(|directory| directory.is_dir())
+            .collect();
+
+        directories.sort();
+        directories.dedup();
+        directories
+    }
+
+    /// Files among `paths` whose content differs from the last time they were read.
+    ///
+    /// Unknown files are only picked up when one of the patterns matches them.
+    /// Existing files matched by `Include` lines new in changed files are reported
+    /// too. Stored hashes are updated, so the same change is reported once.
+    pub fn changed(&mut self, paths: impl IntoIterator<Item = PathBuf>) -> Vec<ChangedFile> {
+        let mut changed = vec![];
+        let mut pending: VecDeque<PathBuf> = paths.into_iter().collect();
+
+        while let Some(path) = pending.pop_front() {
+            let canonical = canonical(&path);
+            let content = fs::read_to_string(&path).ok();
+
+            let (group, origin) = match self.files.get(&canonical) {
+                Some(file) if content.as_deref().map(content_hash).as_ref() == Some(&file.hash) => {
+                    continue
+                }
+                Some(file) => (file.group.clone(), file.origin.clone()),
+                None if content.is_none() => continue,
+                None => match self.pattern_group(&path) {
+                    Some(found) => found,
+                    None => continue,
+                },
+            };
+
+            match &content {
+                Some(content) => {
+                    let added = self.add_file(&SourceFile {
+                        path: canonical.clone(),
+                        group: group.clone(),
+                        content: content.clone(),
+                        origin: origin.clone(),
+                        includes: vec![],
+                    });
+                    for pattern in added {
+                        pending.extend(self.includes.resolve(&pattern));
+                    }
+                }
+                None => {
+                    self.files.remove(&canonical);
+                }
+            }
+
+            changed.push(ChangedFile {
+                path: canonical,
+                group,
+                origin,
+                content,
+            });
+        }
+
+        changed
+    }
+
+    /// Files of `self` which aren't part of `other`.
+    pub fn dropped(&self, other: &WatchSet) -> Vec<PathBuf> {
+        self.files
+            .keys()
+            .filter(|path| !other.files.contains_key(*path))
+            .cloned()
+            .collect()
+    }
+
+    /// Group and source of new file at `path`, `None` when no pattern matches it.
+    fn pattern_group(&self, path: &Path) -> Option<(Option<String>, Option<Source>)> {
+        if crate::is_excluded(path, &self.excludes) {
+            return None;
+        }
+
+        self.patterns
+            .iter()
+            .find(|p| p.pattern.matches_path(path))
+            .map(|p| (groupname_from_path(path, &p.base), p.origin.clone()))
+    }
+}
+
+enum Message {
+    Files(notify::Result<Event>),
+    Config(ConfigEvent),
+}
+
+/// Re-ingests configured ssh config files whenever they change, blocks until the
+/// watcher fails.
+///
+/// Configuration events from `events` are applied between file changes, so ingest
+/// never runs against half applied configuration. Reports of every re-ingest are
+/// passed to `on_change`, failed re-ingest is reported there as diagnostics and
+/// watching goes on. Configuration which can't be applied keeps the previous
+/// database and watched files.
+pub fn watch(
+    db: &mut DbRuntime,
+    configuration: Arc<Mutex<AppConfig>>,
+    events: mpsc::Receiver<ConfigEvent>,
+    mut on_change: impl FnMut(IngestReport),
+) -> Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let files = tx.clone();
+    let mut watcher = RecommendedWatcher::new(
+        move |event| {
+            let _ = files.send(Message::Files(event));
+        },
+        notify::Config::default(),
+    )
+    .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+    std::thread::spawn(move || {
+        for event in events {
+            if tx.send(Message::Config(event)).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut set = crate::watch_set(configuration.clone())?;
+    let mut watched = HashSet::new();
+    let mut diagnostics = vec![];
+    watch_directories(&mut watcher, &set, &mut watched, &mut diagnostics);
+    if !diagnostics.is_empty() {
+        on_change(IngestReport {
+            diagnostics,
+            ..Default::default()
+        });
+    }
+
+    while let Ok(message) = rx.recv() {
+        let mut paths = HashSet::new();
+        let mut config = None;
+        collect(message, &mut paths, &mut config);
+        while let Ok(message) = rx.recv_timeout(DEBOUNCE) {
+            collect(message, &mut paths, &mut config);
+        }
+
+        if let Some(event) = config {
+            let mut diagnostics = vec![];
+            if event.contains(ConfigChange::Storage) {
+                // Failed reconnect keeps the previous database
+                let storage = event.storage();
+                if let Err(e) = db.reconnect(&storage) {
+                    diagnostics.push(Diagnostic::error(
+                        &storage.expanded_path().unwrap_or_default(),
+                        0,
+                        0,
+                        format!("can't reconnect database, previous one is kept: {}", e),
+                    ));
+                }
+            }
+
+            // Failed scan keeps watching files of the previous configuration
+            let dropped = match crate::watch_set(configuration.clone()) {
+                Ok(new) => {
+                    let dropped = set.dropped(&new);
+                    set = new;
+                    for directory in watched.drain() {
+                        let _ = watcher.unwatch(&directory);
+                    }
+                    dropped
+                }
+                Err(e) => {
+                    diagnostics.extend(configuration_error(&configuration, &e));
+                    vec![]
+                }
+            };
+
+            // Full scan covers file changes collected together with the event
+            let rescanned = db
+                .runtime
+                .block_on(rescan(&db.db, configuration.clone(), dropped));
+            let mut report = match rescanned {
+                Ok(report) => report,
+                Err(e) => IngestReport {
+                    diagnostics: configuration_error(&configuration, &e),
+                    ..Default::default()
+                },
+            };
+            report.diagnostics.splice(0..0, diagnostics);
+            watch_directories(&mut watcher, &set, &mut watched, &mut report.diagnostics);
+            on_change(report);
+            continue;
+        }
+
+        let changed = set.changed(paths);
+        if changed.is_empty() {
+            continue;
+        }
+
+        let mut report = match db.runtime.block_on(reingest(&db.db, &changed)) {
+            Ok(report) => report,
+            // Database may be unavailable for a while, next change of the files retries
+            Err(e) => IngestReport {
+                diagnostics: changed
+                    .iter()
+                    .map(|file| {
+                        Diagnostic::error(&file.path, 0, 0, format!("can't ingest changes: {}", e))
+                    })
+                    .collect(),
+                ..Default::default()
+            },
+        };
+        // Changed files may include new directories
+        watch_directories(&mut watcher, &set, &mut watched, &mut report.diagnostics);
+        on_change(report);
+    }
+
+    Ok(())
+}
+
+/// Ingests configured ssh config again, hosts of `dropped` files which are no
+/// longer part of it are removed.
+pub async fn rescan<C: Connection>(
+    db: &Surreal<C>,
+    configuration: Arc<Mutex<AppConfig>>,
+    dropped: Vec<PathBuf>,
+) -> Result<IngestReport> {
+    let mut report = crate::read_ssh_config(db, configuration).await?;
+    report.files.extend(dropped);
+    Hosts::sync(db, &mut report).await?;
+
+    Ok(report)
+}
+
+/// Parses changed files and removes hosts which disappeared from them.
+pub async fn reingest<C: Connection>(
+    db: &Surreal<C>,
+    changed: &[ChangedFile],
+) -> Result<IngestReport> {
+    let mut report = IngestReport::default();
+
+    for file in changed {
+        match &file.content {
+            Some(content) => {
+                let source = SourceFile {
+                    path: file.path.clone(),
+                    group: file.group.clone(),
+                    content: content.clone(),
+                    origin: file.origin.clone(),
+                    includes: vec![],
+                };
+                report.extend(Hosts::parse_file(db, &source).await?);
+            }
+            None => report.files.push(file.path.clone()),
+        }
+    }
+
+    Hosts::sync(db, &mut report).await?;
+
+    Ok(report)
+}
+
+/// Canonical path of `path`, deleted files get canonical path of their directory.
+fn canonical(path: &Path) -> PathBuf {
+    match (path.exists(), path.parent(), path.file_name()) {
+        (false, Some(parent), Some(name)) => canonical_path(parent).join(name),
+        _ => canonical_path(path),
+    }
+}
+
+/// Failure which doesn't concern a single file, reported at every configured source.
+fn configuration_error(configuration: &Mutex<AppConfig>, error: &Error) -> Vec<Diagnostic> {
+    crate::get_sources(configuration)
+        .0
+        .iter()
+        .map(|source| {
+            Diagnostic::error(
+                &source.expanded_path(),
+                0,
+                0,
+                format!("can't read ssh config, previous hosts are kept: {}", error),
+            )
+        })
+        .collect()
+}
+
+/// Starts watching directories of `set` which aren't `watched` yet, directories
+/// which can't be watched are reported in `diagnostics`.
+fn watch_directories(
+    watcher: &mut RecommendedWatcher,
+    set: &WatchSet,
+    watched: &mut HashSet<PathBuf>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for directory in set.directories() {
+        if watched.contains(&directory) {
+            continue;
+        }
+        // Nested directories are part of `config.d` hierarchy as well
+        match watcher.watch(&directory, RecursiveMode::Recursive) {
+            Ok(_) => {
+                watched.insert(directory);
+            }
+            Err(e) => diagnostics.push(Diagnostic::warning(
+                &directory,
+                0,
+                0,
+                format!("can't watch directory, its changes are missed: {}", e),
+            )),
+        }
+    }
+}
+
+fn collect(message: Message, paths: &mut HashSet<PathBuf>, config: &mut Option<ConfigEvent>) {
+    match message {
+        Message::Files(Ok(event)) if !event.kind.is_access() => paths.extend(event.paths),
+        Message::Files(Ok(_)) => {}
+        Message::Files(Err(e)) => log::warn!("Watch error: {}", e),
+        // Later event carries the latest configuration, changes of both apply
+        Message::Config(mut event) => {
+            if let Some(previous) = config.take() {
+                for change in previous.changes {
+                    if !event.contains(change) {
+                        event.changes.push(change);
+                    }
+                }
+            }
+            *config = Some(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_changed_files() {
+        let dir = TempDir::new("watch").unwrap();
+        let root = dir.path().join("config");
+        fs::create_dir_all(dir.path().join("config.d/teams")).unwrap();
+        fs::write(&root, "Include config.d/**/*\nHost a\n").unwrap();
+
+        let origin = Source {
+            name: Some(String::from("personal")),
+            ..Default::default()
+        };
+        let includes =
+            IncludeResolver::with_ssh_dir(dir.path().to_path_buf()).with_origin(Some(origin));
+        let excludes = vec![Pattern::new("*.swp").unwrap()];
+        let mut set = WatchSet::new(excludes, includes);
+        for source in set
+            .includes
+            .collect(root.clone(), None, &mut vec![])
+            .unwrap()
+        {
+            set.add_file(&source);
+        }
+        assert_eq!(
+            set.directories(),
+            [canonical_path(dir.path()), dir.path().join("config.d")]
+        );
+
+        // Saving without changes isn't reported
+        fs::write(&root, "Include config.d/**/*\nHost a\n").unwrap();
+        assert!(set.changed([root.clone()]).is_empty());
+
+        let new = dir.path().join("config.d/teams/prod");
+        fs::write(&new, "Host p\n").unwrap();
+        fs::write(dir.path().join("config.d/teams/.prod.swp"), "").unwrap();
+        let changed = set.changed([new.clone(), dir.path().join("config.d/teams/.prod.swp")]);
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0].group.as_deref(), Some("teams/prod"));
+        // New files belong to the source of the `Include` which matches them
+        assert_eq!(
+            changed[0].origin.as_ref().map(Source::name),
+            Some("personal")
+        );
+        assert!(set.changed([new.clone()]).is_empty());
+
+        fs::remove_file(&new).unwrap();
+        let changed = set.changed([new.clone()]);
+        assert_eq!(changed[0].content, None);
+        assert_eq!(changed[0].group.as_deref(), Some("teams/prod"));
+
+        let other = WatchSet::new(vec![], IncludeResolver::new());
+        assert_eq!(set.dropped(&other), [canonical_path(&root)]);
+
+        // Files of a new `Include` are read right away, not on their next change
+        let extra = dir.path().join("extra");
+        fs::write(&extra, "Host e\n").unwrap();
+        fs::write(&root, "Include config.d/**/*\nInclude extra\nHost a\n").unwrap();
+        let changed = set.changed([root.clone()]);
+        let paths: Vec<&Path> = changed.iter().map(|c| c.path.as_path()).collect();
+        assert_eq!(paths, [canonical_path(&root), canonical_path(&extra)]);
+        assert_eq!(changed[1].content.as_deref(), Some("Host e\n"));
+    }
+}
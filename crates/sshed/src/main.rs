@@ -1,36 +1,57 @@
 use std::{
-    path::PathBuf,
     sync::{self, Arc, Mutex},
     time::Duration,
 };
 
 use db::DbRuntime;
-use ui::HelloWorld;
+use ui::{DiagnosticList, HelloWorld, HostDetails, HostList};
 
-use cli::parse_args;
-use config::{read_config, AppConfig};
-use gpui::{App, AppContext, VisualContext, WindowOptions};
-use notify::{
-    event::{DataChange, ModifyKind},
-    Config, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+use cli::{parse_args, print_diagnostics, Command, ConfigCommand, HostCommand, Options};
+use config::{
+    events::{self, ConfigBus},
+    layers, AppConfig,
 };
+use gpui::{App, AppContext, VisualContext, WindowOptions};
+use hosts::{host::EnhancedHost, Hosts};
+use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
 use ssh_parser::{self, SshParser};
 
-fn monitor_cfg_change(path: &PathBuf, appconfig: Arc<Mutex<AppConfig>>) -> notify::Result<()> {
+fn monitor_cfg_change(
+    options: &Options,
+    appconfig: Arc<Mutex<AppConfig>>,
+    bus: Arc<ConfigBus>,
+) -> notify::Result<()> {
     let (tx, rx) = sync::mpsc::channel();
     let mut watcher = RecommendedWatcher::new(
         tx,
         Config::default().with_poll_interval(Duration::from_secs(5)),
     )?;
 
-    watcher.watch(&path, RecursiveMode::NonRecursive)?;
+    // Directories are watched, so layer files created or replaced later are seen too
+    for directory in options.paths.directories() {
+        if let Err(e) = watcher.watch(&directory, RecursiveMode::NonRecursive) {
+            eprintln!("Failed to watch {}: {}", directory.display(), e);
+        }
+    }
+    let files = options.paths.all();
 
     for res in rx {
         match res {
             Ok(event) => {
-                if event.kind == EventKind::Modify(ModifyKind::Data(DataChange::Content)) {
-                    if let Ok(mut config) = appconfig.lock() {
-                        *config = read_config(path);
+                // Edit of any layer changes the merged configuration
+                let layer_changed = event
+                    .paths
+                    .iter()
+                    .any(|path| files.iter().any(|file| path.ends_with(file)));
+                if layer_changed && !event.kind.is_access() {
+                    // Malformed file keeps previous configuration
+                    match layers::load(&options.paths, &options.overrides) {
+                        Ok(layered) => {
+                            if let Some(event) = events::apply(layered.config, &appconfig) {
+                                bus.publish(&event);
+                            }
+                        }
+                        Err(diagnostic) => print_diagnostics(&[diagnostic]),
                     }
                 }
             }
@@ -43,29 +64,122 @@ fn monitor_cfg_change(path: &PathBuf, appconfig: Arc<Mutex<AppConfig>>) -> notif
 fn main() {
     env_logger::init();
 
-    let args = parse_args();
+    let options = parse_args();
 
-    let app = App::new();
+    let layered = match layers::load(&options.paths, &options.overrides) {
+        Ok(layered) => layered,
+        Err(diagnostic) => {
+            print_diagnostics(&[diagnostic]);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(Command::Config(ConfigCommand::Show { origin })) = &options.command {
+        print!("{}", layered.show(*origin));
+        return;
+    }
 
-    let cfg = match args {
-        Ok(ref pth) => Arc::new(Mutex::new(read_config(pth))),
+    if let Some(Command::Host(HostCommand::Show {
+        name,
+        effective: true,
+    })) = &options.command
+    {
+        // Read straight from ssh config files, database isn't needed
+        match SshParser::resolve(Arc::new(Mutex::new(layered.config.clone())), name) {
+            Ok(effective) => {
+                print_diagnostics(&effective.diagnostics);
+                print!("{}", effective);
+                for origin in &effective.undetermined {
+                    println!("# skipped, evaluated by ssh when connecting: {}", origin);
+                }
+            }
+            Err(e) => {
+                eprintln!("Error reading ssh config: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let app = App::new();
+    // Local path opens embedded database, `host:port` connects to a server
+    let storage = layered
+        .config
+        .general
+        .as_ref()
+        .and_then(|g| g.storage.clone())
+        .unwrap_or_default();
+    let mut db = match DbRuntime::new(&storage) {
+        Ok(db) => db,
         Err(e) => {
-            eprint!("{}", e);
+            eprintln!("Error opening database: {}", e);
             std::process::exit(1);
         }
     };
+    let cfg = Arc::new(Mutex::new(layered.config));
+
+    if let Some(Command::Host(HostCommand::Show { name, .. })) = &options.command {
+        // Ingest first, so the host is shown as ssh config currently defines it
+        match SshParser::init(&db, cfg.clone()) {
+            Ok(report) => print_diagnostics(&report.diagnostics),
+            Err(e) => eprintln!("Error reading ssh config: {}", e),
+        }
+        match db.runtime.block_on(Hosts::describe(&db.db, name)) {
+            Ok(Some(host)) => print!("{}", host),
+            Ok(None) => {
+                eprintln!("No host is known as `{}`", name);
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Error reading host: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Subsystems subscribe to apply reloaded configuration, before the monitor
+    // starts publishing so no reload is missed
+    let bus = Arc::new(ConfigBus::new());
+    let config_events = bus.channel();
 
     app.run(move |cx: &mut AppContext| {
         let config_clone = Arc::clone(&cfg);
-        let args_clone = args.unwrap();
+        let bus_clone = Arc::clone(&bus);
         std::thread::spawn(move || {
-            if let Err(e) = monitor_cfg_change(&args_clone, config_clone) {
+            if let Err(e) = monitor_cfg_change(&options, config_clone, bus_clone) {
                 eprintln!("Error monitoring file: {}", e);
             }
         });
 
-        let db = DbRuntime::new();
-        SshParser::init(db, cfg.clone()).unwrap();
+        let report = match SshParser::init(&db, cfg.clone()) {
+            Ok(report) => report,
+            Err(e) => {
+                eprintln!("Error reading ssh config: {}", e);
+                Default::default()
+            }
+        };
+        print_diagnostics(&report.diagnostics);
+
+        let records = match db.runtime.block_on(EnhancedHost::get_all(&db.db)) {
+            Ok(records) => records,
+            Err(e) => {
+                eprintln!("Error reading hosts: {}", e);
+                vec![]
+            }
+        };
+
+        // Database follows edits of ssh config files from now on
+        let config_clone = Arc::clone(&cfg);
+        std::thread::spawn(move || {
+            let result = SshParser::watch(&mut db, config_clone, config_events, |report| {
+                print_diagnostics(&report.diagnostics);
+                print!("{}", report.changes);
+            });
+            if let Err(e) = result {
+                eprintln!("Error watching ssh config: {}", e);
+            }
+        });
 
         cx.open_window(WindowOptions::default(), |cx| {
             cx.new_view(|_cx| HelloWorld {
@@ -73,5 +187,25 @@ fn main() {
             })
         })
         .unwrap();
+
+        if !records.is_empty() {
+            cx.open_window(WindowOptions::default(), |cx| {
+                let hosts = records
+                    .into_iter()
+                    .map(|record| cx.new_view(|_cx| HostDetails { record }))
+                    .collect();
+                cx.new_view(|_cx| HostList { hosts })
+            })
+            .unwrap();
+        }
+
+        if !report.diagnostics.is_empty() {
+            cx.open_window(WindowOptions::default(), |cx| {
+                cx.new_view(|_cx| DiagnosticList {
+                    diagnostics: report.diagnostics,
+                })
+            })
+            .unwrap();
+        }
     });
 }
@@ -1,4 +1,6 @@
-use gpui::{div, rgb, IntoElement, ParentElement, Render, SharedString, Styled, ViewContext};
+use diagnostics::{Diagnostic, Severity};
+use gpui::{div, rgb, IntoElement, ParentElement, Render, SharedString, Styled, View, ViewContext};
+use hosts::host::{writer, HostRecord};
 
 pub struct HelloWorld {
     pub text: SharedString,
@@ -17,3 +19,122 @@ impl Render for HelloWorld {
             .child(format!("Hello, {}!", &self.text))
     }
 }
+
+/// Problems found while reading configuration and ssh config files.
+pub struct DiagnosticList {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Render for DiagnosticList {
+    fn render(&mut self, _cx: &mut ViewContext<Self>) -> impl IntoElement {
+        div()
+            .flex()
+            .flex_col()
+            .size_full()
+            .p_2()
+            .gap_1()
+            .bg(rgb(0x1e1e1e))
+            .text_sm()
+            .children(self.diagnostics.iter().map(|diagnostic| {
+                let color = match diagnostic.severity {
+                    Severity::Error => rgb(0xf44336),
+                    Severity::Warning => rgb(0xffb300),
+                };
+
+                div()
+                    .flex()
+                    .flex_col()
+                    .child(
+                        div()
+                            .text_color(color)
+                            .child(format!("{}: {}", diagnostic.severity, diagnostic.message)),
+                    )
+                    .child(div().text_color(rgb(0x9e9e9e)).child(diagnostic.location()))
+            }))
+    }
+}
+
+/// Every ingested host, each one shown by its [`HostDetails`].
+pub struct HostList {
+    pub hosts: Vec<View<HostDetails>>,
+}
+
+impl Render for HostList {
+    fn render(&mut self, _cx: &mut ViewContext<Self>) -> impl IntoElement {
+        div()
+            .flex()
+            .flex_col()
+            .size_full()
+            .gap_2()
+            .bg(rgb(0x1e1e1e))
+            .children(self.hosts.iter().cloned())
+    }
+}
+
+/// Options of a single host together with its description and option comments.
+pub struct HostDetails {
+    pub record: HostRecord,
+}
+
+impl Render for HostDetails {
+    fn render(&mut self, _cx: &mut ViewContext<Self>) -> impl IntoElement {
+        let comment = rgb(0x9e9e9e);
+        let mut commented: Vec<String> = vec![];
+        // Written in the same order as their options
+        let host = &self.record.host;
+        let mut files = host.identity_files().into_iter().chain(host.certificate());
+
+        let options = writer::options(host)
+            .into_iter()
+            .map(|option| {
+                let keyword = option.keyword.to_lowercase();
+                // Comment of repeated option is shown once, above the first one
+                let note = if commented.contains(&keyword) {
+                    None
+                } else {
+                    commented.push(keyword.clone());
+                    self.record.option_comments.get(&keyword).cloned()
+                };
+                // Files ssh opens are shown below paths as written
+                let expanded = match keyword.as_str() {
+                    "identityfile" | "certificatefile" => files
+                        .next()
+                        .filter(|file| file.expanded.to_string_lossy() != file.raw)
+                        .map(|file| file.expanded.display().to_string()),
+                    _ => None,
+                };
+
+                div()
+                    .flex()
+                    .flex_col()
+                    .children(
+                        note.into_iter()
+                            .flat_map(|note| note.lines().map(String::from).collect::<Vec<_>>())
+                            .map(|line| div().text_color(comment).child(format!("# {}", line))),
+                    )
+                    .child(div().child(option.to_string()))
+                    .children(
+                        expanded.map(|path| div().text_color(comment).child(format!("  {}", path))),
+                    )
+            });
+
+        div()
+            .flex()
+            .flex_col()
+            .size_full()
+            .p_2()
+            .gap_1()
+            .bg(rgb(0x1e1e1e))
+            .text_sm()
+            .text_color(rgb(0xffffff))
+            .child(div().text_xl().child(self.record.host.name.clone()))
+            .children(
+                self.record
+                    .comment
+                    .iter()
+                    .flat_map(|c| c.lines().map(String::from).collect::<Vec<_>>())
+                    .map(|line| div().text_color(comment).child(line)),
+            )
+            .children(options.collect::<Vec<_>>())
+    }
+}